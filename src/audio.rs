@@ -0,0 +1,79 @@
+use crate::synth::{Synth, Tone};
+use std::time::Duration;
+
+/**
+ * Where emulated sound goes, e.g. the sound card.
+ * Samples are mono, -1.0 ~ 1.0.
+ */
+pub trait AudioSink {
+    // samples per second the sink expects
+    fn sample_rate(&self) -> u32;
+
+    fn write(&mut self, samples: &[f32]);
+
+    // does the user hear it right away? the mute hotkey only silences these
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    // sound waiting to be played, for sinks that play in real time
+    fn buffered(&self) -> Option<Duration> {
+        None
+    }
+
+    // called once after the last samples, e.g. to complete a file
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/**
+ * Beeper, sounding while the sound timer is nonzero
+ *
+ * Samples are generated in emulated time: every instruction adds
+ * 1 / fps seconds of sound, whatever time it really took. They are
+ * collected until `flush` hands them to a sink.
+ */
+pub struct Beeper {
+    pub synth: Synth,
+
+    // emulated time not turned into samples yet, in 1 / (sample rate * fps) s
+    clock: u64,
+
+    samples: Vec<f32>,
+}
+
+impl Beeper {
+    pub fn new(tone: Tone) -> Self {
+        Beeper {
+            synth: Synth::new(tone),
+            clock: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    /**
+     * generate the sound of one instruction, with the emulator running
+     * `fps` instructions per second.
+     * on: is the beep sounding?
+     * sample_rate: samples per second of the sink the sound is for
+     */
+    pub fn run(&mut self, on: bool, fps: u32, sample_rate: u32) {
+        let fps = fps.max(1) as u64;
+        self.clock += sample_rate as u64;
+
+        // one instruction is sample_rate / fps samples, usually not
+        // a whole number. the remainder carries over to the next one
+        while self.clock >= fps {
+            self.clock -= fps;
+            let sample = self.synth.sample(on, sample_rate as f32);
+            self.samples.push(sample);
+        }
+    }
+
+    // pass the samples generated so far on to `sink`
+    pub fn flush(&mut self, sink: &mut dyn AudioSink) {
+        sink.write(&self.samples);
+        self.samples.clear();
+    }
+}
@@ -0,0 +1,208 @@
+#[cfg(feature = "sdl")]
+use crate::filter::Filter;
+use crate::keymap::Keymap;
+use crate::palette::{self, Palette};
+use crate::phosphor::Persistence;
+use crate::quirks::Quirks;
+use crate::storage;
+use crate::synth::{Tone, Waveform};
+use std::fs;
+use std::path::PathBuf;
+use toml::Value;
+
+/**
+ * Settings from the config file. Command line options take precedence.
+ *
+ * The file is <config dir>/config.toml unless --config is given, e.g.
+ *
+ *   # a preset, or a list of 2 to 4 colors
+ *   palette = "amber"
+ *   palette = ["#000000", "#33ff66"]
+ *
+ *   # "off", "blend" or the number of frames pixels take to fade out
+ *   persistence = 4
+ *
+ *   # a preset (qwerty, azerty, numpad, octo) or the path of a keymap file
+ *   keymap = "octo"
+ *
+ *   # "none", "crt" or a list of effects
+ *   filter = ["scanlines", "bloom"]
+ *
+ *   # quirks to turn on or off. the others keep their defaults
+ *   [quirks]
+ *   key-release = true
+ *   key-beep = true
+ *
+ *   # the beep. all keys are optional
+ *   [sound]
+ *   waveform = "triangle"   # square, triangle, sine or noise
+ *   frequency = 440         # Hz
+ *   volume = 0.25           # 0.0 ~ 1.0
+ *   attack = 2              # milliseconds to fade in
+ *   release = 2             # milliseconds to fade out
+ */
+#[derive(Default)]
+pub struct Config {
+    pub palette: Option<Palette>,
+    pub persistence: Option<Persistence>,
+    #[cfg(feature = "sdl")]
+    pub filter: Option<Filter>,
+    pub tone: Tone,
+    pub keymap: Option<Keymap>,
+    pub quirks: Quirks,
+}
+
+impl Config {
+    /**
+     * read the config file at `path`, or the default one if `path` is None.
+     * a missing default config file is not an error.
+     */
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let path = storage::config_dir()?.join("config.toml");
+                if !path.exists() {
+                    return Ok(Config::default());
+                }
+                path
+            }
+        };
+
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let table = text.parse::<Value>().map_err(|e| e.to_string())?;
+        let mut config = Config::default();
+
+        if let Some(value) = table.get("palette") {
+            config.palette = Some(parse_palette(value)?);
+        }
+
+        if let Some(value) = table.get("persistence") {
+            config.persistence = Some(parse_persistence(value)?);
+        }
+
+        #[cfg(feature = "sdl")]
+        if let Some(value) = table.get("filter") {
+            config.filter = Some(parse_filter(value)?);
+        }
+
+        match table.get("keymap") {
+            None => (),
+            Some(Value::String(keymap)) => config.keymap = Some(Keymap::load(keymap)?),
+            Some(_) => return Err(String::from("keymap must be a preset or a file name")),
+        }
+
+        if let Some(value) = table.get("quirks") {
+            config.quirks = parse_quirks(value)?;
+        }
+
+        if let Some(value) = table.get("sound") {
+            config.tone = parse_sound(value)?;
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_palette(value: &Value) -> Result<Palette, String> {
+    match value {
+        Value::String(name) => Palette::parse(name),
+        Value::Array(colors) => {
+            let colors = colors
+                .iter()
+                .map(|color| match color {
+                    Value::String(color) => palette::parse_color(color),
+                    _ => Err(String::from("palette colors must be strings")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Palette::from_colors(&colors)
+        }
+        _ => Err(String::from("palette must be a name or a list of colors")),
+    }
+}
+
+fn parse_sound(value: &Value) -> Result<Tone, String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| String::from("sound must be a table"))?;
+    let mut tone = Tone::default();
+
+    // whole numbers are fine where fractions are expected
+    let number = |key: &str| -> Result<Option<f32>, String> {
+        match table.get(key) {
+            None => Ok(None),
+            Some(Value::Float(value)) => Ok(Some(*value as f32)),
+            Some(Value::Integer(value)) => Ok(Some(*value as f32)),
+            Some(_) => Err(format!("sound.{} must be a number", key)),
+        }
+    };
+
+    match table.get("waveform") {
+        None => (),
+        Some(Value::String(waveform)) => tone.waveform = Waveform::parse(waveform)?,
+        Some(_) => return Err(String::from("sound.waveform must be a string")),
+    }
+    if let Some(frequency) = number("frequency")? {
+        tone.frequency = frequency;
+    }
+    if let Some(volume) = number("volume")? {
+        tone.volume = volume;
+    }
+    if let Some(attack) = number("attack")? {
+        tone.attack = attack / 1000.0;
+    }
+    if let Some(release) = number("release")? {
+        tone.release = release / 1000.0;
+    }
+
+    Ok(tone)
+}
+
+fn parse_quirks(value: &Value) -> Result<Quirks, String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| String::from("quirks must be a table"))?;
+    let mut quirks = Quirks::default();
+
+    for (name, value) in table.iter() {
+        match value {
+            Value::Boolean(on) => quirks.set(name, *on)?,
+            _ => return Err(format!("quirks.{} must be true or false", name)),
+        }
+    }
+
+    Ok(quirks)
+}
+
+fn parse_persistence(value: &Value) -> Result<Persistence, String> {
+    match value {
+        Value::String(value) => Persistence::parse(value),
+        Value::Integer(0) => Ok(Persistence::Off),
+        Value::Integer(frames @ 1..=255) => Ok(Persistence::Decay(*frames as u8)),
+        _ => Err(String::from(
+            "persistence must be \"off\", \"blend\" or a number of frames (0 ~ 255)",
+        )),
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn parse_filter(value: &Value) -> Result<Filter, String> {
+    match value {
+        Value::String(value) => Filter::parse(value),
+        Value::Array(effects) => {
+            let effects = effects
+                .iter()
+                .map(|effect| match effect {
+                    Value::String(effect) => Ok(effect.as_str()),
+                    _ => Err(String::from("filter effects must be strings")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Filter::parse(&effects.join(","))
+        }
+        _ => Err(String::from("filter must be a name or a list of effects")),
+    }
+}
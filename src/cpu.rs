@@ -1,9 +1,29 @@
 use crate::font;
+use crate::input::InputQueue;
 use crate::lib::*;
-use rand::Rng;
+use crate::quirks::Quirks;
+use crate::rng::{RandomSource, SeededRng};
+use crate::state::State;
+use crate::synth::Pattern;
 
 const DEBUG: bool = false;
 
+// instructions run per second. the timers tick 60 times in as many cycles
+pub const FPS: u32 = 1000;
+
+// sound timer value FX0A keeps up while a key is held, with the key-beep quirk
+const KEY_BEEP: u8 = 4;
+
+// progress of FX0A waiting for a key
+#[derive(Clone, Copy)]
+struct KeyWait {
+    // keys that were already held when FX0A started, until they are released
+    ignored: [bool; 16],
+
+    // the key pressed since, waiting to be released
+    pressed: Option<usize>,
+}
+
 pub struct Cpu {
     // opcode is two bytes long
     opcode: u16,
@@ -60,11 +80,40 @@ pub struct Cpu {
      * |---|---|---|---|
      * | A | 0 | B | F |
      * |---|---|---|---|
+     *
+     * changed only by the events of `input`
      */
-    pub keyboard: [bool; 16],
+    keyboard: [bool; 16],
+
+    // key presses and releases, applied at the cycle they are due
+    pub input: InputQueue,
+
+    // instructions run so far
+    cycles: u64,
 
     // should the display be redrawn?
     pub redraw: bool,
+
+    // SCHIP RPL user flags. SCHIP uses 8 of them, XO-CHIP all 16
+    rpl: [u8; 16],
+
+    // were the RPL flags written since the last time they were saved?
+    pub rpl_changed: bool,
+
+    // source of random bytes for CXNN
+    rng: Box<dyn RandomSource>,
+
+    // XO-CHIP audio: 128 1-bit samples loaded by F002, played while the
+    // sound timer runs. None until a ROM loads one, which plays the beep
+    pattern: Option<[u8; 16]>,
+
+    // XO-CHIP playback rate of the pattern, set by FX3A
+    pitch: u8,
+
+    pub quirks: Quirks,
+
+    // FX0A in progress, if any
+    key_wait: Option<KeyWait>,
 }
 
 impl Cpu {
@@ -81,8 +130,17 @@ impl Cpu {
             stack: [0; 16],
             sp: 0,
             keyboard: [false; 16],
+            input: InputQueue::new(),
+            cycles: 0,
             redraw: false,
+            rpl: [0; 16],
+            rpl_changed: false,
             timer_cycles: 0,
+            rng: Box::new(SeededRng::from_entropy()),
+            pattern: None,
+            pitch: Pattern::DEFAULT_PITCH,
+            quirks: Quirks::default(),
+            key_wait: None,
         };
 
         cpu.load_fonts();
@@ -105,38 +163,119 @@ impl Cpu {
         }
     }
 
+    // replace the source of random bytes used by CXNN
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl
+    }
+
+    // restore RPL flags, e.g. the ones saved by a previous session
+    pub fn set_rpl_flags(&mut self, flags: &[u8]) {
+        let len = flags.len().min(16);
+        self.rpl = [0; 16];
+        self.rpl[..len].copy_from_slice(&flags[..len]);
+    }
+
     // load rom into memory. starts at 0x200
     pub fn load_rom(&mut self, buf: &Vec<u8>) {
         for (index, &c) in buf.iter().enumerate() {
             self.memory[0x200 + index] = c;
         }
         self.pc = 0x200;
+        self.key_wait = None;
     }
 
     /**
      * update_timers
      * fps: at what frame rate is this emulator running at?
+     * returns true if the timers ticked, i.e. once every 1/60 s
      */
-    pub fn update_timers(&mut self, fps: u32) {
-        self.timer_cycles += 1;
+    pub fn update_timers(&mut self, fps: u32) -> bool {
+        self.timer_cycles += 60;
 
         // the timers should be updated at 60fps speed.
         // since emulating 60fps shows very laggy gameplay,
         // this emulator allows custom fps (1000fps by default)
         // so use a custom cycle variable to stay true to the original
-        // update rate
-        if self.timer_cycles == (fps / 60) {
+        // update rate. it counts cycles times 60, so that the timers tick
+        // exactly 60 times per `fps` cycles even if fps is not a multiple of 60
+        if self.timer_cycles >= fps {
             if self.delay_timer > 0 {
                 self.delay_timer -= 1;
             }
 
+            // the emulator beeps while the sound timer is nonzero
             if self.sound_timer > 0 {
                 self.sound_timer -= 1;
-                // TODO: play sound. to be implemented
             }
 
-            self.timer_cycles = 0;
+            self.timer_cycles -= fps;
+            return true;
         }
+
+        false
+    }
+
+    // instructions run so far, the clock of `input`
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // the XO-CHIP sound to play instead of the beep, if a ROM loaded one
+    pub fn audio_pattern(&self) -> Option<Pattern> {
+        self.pattern.map(|bits| Pattern {
+            bits,
+            pitch: self.pitch,
+        })
+    }
+
+    // width of the display in pixels. the height follows from the size of gfx
+    pub fn display_width(&self) -> usize {
+        64
+    }
+
+    // take a snapshot of the machine
+    pub fn save_state(&self) -> State {
+        State {
+            memory: self.memory,
+            reg: self.reg,
+            index: self.index,
+            pc: self.pc,
+            gfx: self.gfx,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            timer_cycles: self.timer_cycles,
+            stack: self.stack,
+            sp: self.sp,
+            rpl: self.rpl,
+            rng: self.rng.save(),
+            pattern: self.pattern,
+            pitch: self.pitch,
+        }
+    }
+
+    // continue from a snapshot taken by `save_state`
+    pub fn load_state(&mut self, state: &State) {
+        self.memory = state.memory;
+        self.reg = state.reg;
+        self.index = state.index;
+        self.pc = state.pc;
+        self.gfx = state.gfx;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.timer_cycles = state.timer_cycles;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.rpl = state.rpl;
+        self.rng.restore(&state.rng);
+        self.pattern = state.pattern;
+        self.pitch = state.pitch;
+        self.key_wait = None;
+
+        self.redraw = true;
     }
 
     pub fn dump_state(&mut self) {
@@ -164,6 +303,11 @@ impl Cpu {
             self.dump_state();
         }
 
+        while let Some(event) = self.input.pop(self.cycles) {
+            self.keyboard[event.key] = event.pressed;
+        }
+        self.cycles += 1;
+
         let opcode = (self.memory[self.pc as usize] as u16) << 8
             | self.memory[(self.pc + 1) as usize] as u16;
         self.opcode = opcode;
@@ -210,6 +354,7 @@ impl Cpu {
                 _ => panic!("Invalid Instruction {}", opcode),
             },
             0xF => match lower_two_nibbles {
+                0x02 if opcode == 0xF002 => self.ld_pattern(),
                 0x07 => self.ld_reg_dt(),
                 0x0A => self.ld_reg_key(),
                 0x15 => self.ld_dt_reg(),
@@ -217,8 +362,11 @@ impl Cpu {
                 0x1E => self.add_index_reg(),
                 0x29 => self.ld_sprite_reg(),
                 0x33 => self.ld_bcd_reg(),
+                0x3A => self.ld_pitch_reg(),
                 0x55 => self.ld_indirect_reg(),
                 0x65 => self.ld_reg_indirect(),
+                0x75 => self.ld_rpl_reg(),
+                0x85 => self.ld_reg_rpl(),
                 _ => panic!("Invalid Instruction {}", opcode),
             },
             _ => unimplemented!(),
@@ -487,8 +635,7 @@ impl Cpu {
 
     // AND random value with value
     fn rnd_reg_byte(&mut self) {
-        let mut rng = rand::thread_rng();
-        let rand_byte: u8 = rng.gen_range(0..=255);
+        let rand_byte = self.rng.next_byte();
 
         let x = get_nth_nibble(self.opcode, 3);
         let kk = (self.opcode & 0x00FF) as u8;
@@ -584,20 +731,47 @@ impl Cpu {
         self.pc += 2;
     }
 
+    /**
+     * wait for a key and store it in VX. runs again every cycle until done.
+     *
+     * only a key pressed after FX0A started counts, so holding a key does
+     * not skip through menus. with the key-release quirk the key also has
+     * to be released, like on the COSMAC VIP.
+     */
     fn ld_reg_key(&mut self) {
-        let pressed_key_index = self.keyboard.iter().position(|&x| x);
-
-        match pressed_key_index {
-            Some(index) => {
-                assert!(index < 16);
+        let keyboard = self.keyboard;
+        let wait = self.key_wait.get_or_insert(KeyWait {
+            ignored: keyboard,
+            pressed: None,
+        });
+
+        // a key held from before counts again once it was let go
+        for (ignored, &held) in wait.ignored.iter_mut().zip(keyboard.iter()) {
+            *ignored &= held;
+        }
 
-                let x = get_nth_nibble(self.opcode, 3) as usize;
-                self.reg[x] = index as u8;
+        if wait.pressed.is_none() {
+            wait.pressed = (0..16).find(|&index| keyboard[index] && !wait.ignored[index]);
+        }
 
-                self.pc += 2;
-            }
-            None => (), // pass until key is pressed
+        let index = match wait.pressed {
+            Some(index) => index,
+            None => return, // pass until key is pressed
         };
+
+        if keyboard[index] && self.quirks.key_beep {
+            self.sound_timer = self.sound_timer.max(KEY_BEEP);
+        }
+
+        if keyboard[index] && self.quirks.key_release {
+            return; // pass until key is released
+        }
+
+        let x = get_nth_nibble(self.opcode, 3) as usize;
+        self.reg[x] = index as u8;
+        self.key_wait = None;
+
+        self.pc += 2;
     }
 
     fn ld_dt_reg(&mut self) {
@@ -665,4 +839,43 @@ impl Cpu {
 
         self.pc += 2;
     }
+
+    // store V0 ~ Vx in the RPL user flags
+    fn ld_rpl_reg(&mut self) {
+        let x = get_nth_nibble(self.opcode, 3) as usize;
+
+        self.rpl[..=x].copy_from_slice(&self.reg[..=x]);
+        self.rpl_changed = true;
+
+        self.pc += 2;
+    }
+
+    // read V0 ~ Vx from the RPL user flags
+    fn ld_reg_rpl(&mut self) {
+        let x = get_nth_nibble(self.opcode, 3) as usize;
+
+        self.reg[..=x].copy_from_slice(&self.rpl[..=x]);
+
+        self.pc += 2;
+    }
+
+    // XO-CHIP: load the 16 bytes at I into the audio pattern buffer
+    fn ld_pattern(&mut self) {
+        let mut bits = [0; 16];
+        for (i, byte) in bits.iter_mut().enumerate() {
+            *byte = self.memory[(self.index as usize + i) & 0xFFF];
+        }
+        self.pattern = Some(bits);
+
+        self.pc += 2;
+    }
+
+    // XO-CHIP: set the playback rate of the audio pattern to Vx
+    fn ld_pitch_reg(&mut self) {
+        let x = get_nth_nibble(self.opcode, 3) as usize;
+
+        self.pitch = self.reg[x];
+
+        self.pc += 2;
+    }
 }
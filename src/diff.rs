@@ -0,0 +1,193 @@
+use crate::state::State;
+use std::path::Path;
+
+const USAGE: &str = "Usage: ./scaters state-diff <a.state> <b.state>";
+
+/**
+ * `scaters state-diff a.state b.state`
+ *
+ * Print everything that differs between two save states: registers, timers,
+ * XO-CHIP audio, stack, RPL flags, memory (grouped into ranges) and the display.
+ */
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.len() != 2 {
+        return Err(String::from(USAGE));
+    }
+
+    let a = State::read(Path::new(&args[0]))?;
+    let b = State::read(Path::new(&args[1]))?;
+
+    print!("{}", diff(&a, &b));
+    Ok(())
+}
+
+/**
+ * describe the differences between two states, `a` being the "before"
+ * state and `b` the "after" state.
+ */
+pub fn diff(a: &State, b: &State) -> String {
+    let mut out = String::new();
+
+    let mut registers = Vec::new();
+    for i in 0..16 {
+        if a.reg[i] != b.reg[i] {
+            registers.push(format!("V{:X}: {:#04x} -> {:#04x}", i, a.reg[i], b.reg[i]));
+        }
+    }
+    if a.index != b.index {
+        registers.push(format!("I: {:#05x} -> {:#05x}", a.index, b.index));
+    }
+    if a.pc != b.pc {
+        registers.push(format!("PC: {:#05x} -> {:#05x}", a.pc, b.pc));
+    }
+    section(&mut out, "Registers", &registers);
+
+    let mut timers = Vec::new();
+    if a.delay_timer != b.delay_timer {
+        timers.push(format!("Delay: {} -> {}", a.delay_timer, b.delay_timer));
+    }
+    if a.sound_timer != b.sound_timer {
+        timers.push(format!("Sound: {} -> {}", a.sound_timer, b.sound_timer));
+    }
+    section(&mut out, "Timers", &timers);
+
+    let mut audio = Vec::new();
+    if a.pattern != b.pattern {
+        let pattern = |pattern: &Option<[u8; 16]>| match pattern {
+            Some(pattern) => hex(pattern),
+            None => String::from("none"),
+        };
+        audio.push(format!("Pattern: {}", pattern(&a.pattern)));
+        audio.push(format!("     -> {}", pattern(&b.pattern)));
+    }
+    if a.pitch != b.pitch {
+        audio.push(format!("Pitch: {} -> {}", a.pitch, b.pitch));
+    }
+    section(&mut out, "Audio", &audio);
+
+    let mut stack = Vec::new();
+    if a.sp != b.sp {
+        stack.push(format!("SP: {} -> {}", a.sp, b.sp));
+    }
+    // only entries below the stack pointer are in use
+    let depth = a.sp.max(b.sp) as usize;
+    for i in 0..depth.min(16) {
+        if a.stack[i] != b.stack[i] {
+            stack.push(format!(
+                "[{}]: {:#05x} -> {:#05x}",
+                i, a.stack[i], b.stack[i]
+            ));
+        }
+    }
+    section(&mut out, "Stack", &stack);
+
+    let mut flags = Vec::new();
+    for i in 0..16 {
+        if a.rpl[i] != b.rpl[i] {
+            flags.push(format!("R{:X}: {:#04x} -> {:#04x}", i, a.rpl[i], b.rpl[i]));
+        }
+    }
+    section(&mut out, "RPL flags", &flags);
+
+    let ranges = changed_ranges(&a.memory, &b.memory);
+    let mut memory = Vec::new();
+    for &(start, end) in ranges.iter() {
+        memory.push(format!(
+            "{:#05x}-{:#05x} ({} bytes)",
+            start,
+            end - 1,
+            end - start
+        ));
+        memory.push(format!("  a: {}", hex(&a.memory[start..end])));
+        memory.push(format!("  b: {}", hex(&b.memory[start..end])));
+    }
+    section(&mut out, "Memory", &memory);
+
+    let changed_pixels = a
+        .gfx
+        .iter()
+        .zip(b.gfx.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    if changed_pixels > 0 {
+        out.push_str(&format!("Display: {} pixels differ\n", changed_pixels));
+        out.push_str("  (# on in both, - only in a, + only in b)\n");
+        out.push_str(&display_diff(&a.gfx, &b.gfx));
+    }
+
+    if out.is_empty() {
+        out.push_str("States are identical\n");
+    }
+
+    out
+}
+
+fn section(out: &mut String, title: &str, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+
+    out.push_str(title);
+    out.push_str(":\n");
+    for line in lines {
+        out.push_str("  ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('\n');
+}
+
+/**
+ * find runs of differing bytes.
+ * returns (start, end) pairs, end being exclusive.
+ */
+fn changed_ranges(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+
+    for i in 0..a.len() {
+        match (start, a[i] != b[i]) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                ranges.push((s, i));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(s) = start {
+        ranges.push((s, a.len()));
+    }
+
+    ranges
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// draw both displays on top of each other
+fn display_diff(a: &[bool], b: &[bool]) -> String {
+    let mut out = String::new();
+
+    for h in 0..32 {
+        out.push_str("  ");
+        for w in 0..64 {
+            let index = h * 64 + w;
+            out.push(match (a[index], b[index]) {
+                (true, true) => '#',
+                (true, false) => '-',
+                (false, true) => '+',
+                (false, false) => '.',
+            });
+        }
+        out.push('\n');
+    }
+
+    out
+}
@@ -1,89 +1,951 @@
-use crate::cpu::Cpu;
-use crate::gfx::Graphics;
-use crate::lib;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::time::Duration;
+use crate::audio::{AudioSink, Beeper};
+use crate::cpu::{Cpu, FPS};
+#[cfg(feature = "sdl")]
+use crate::filter::Filter;
+use crate::frame::Frame;
+#[cfg(feature = "sdl")]
+use crate::gamepad::{Gamepads, PadInput};
+#[cfg(feature = "sdl")]
+use crate::gfx::{Graphics, Scaling};
+use crate::keymap::Keymap;
+use crate::macros::{self, Action, Turbo};
+use crate::pacing::{Pacer, Pacing};
+use crate::palette::Palette;
+use crate::phosphor::{Persistence, Phosphor};
+use crate::quirks::Quirks;
+use crate::recorder::GifRecorder;
+use crate::rng::RandomSource;
+use crate::screenshot;
+#[cfg(feature = "sdl")]
+use crate::sdl_audio::SdlAudio;
+use crate::state::State;
+use crate::storage;
+use crate::synth::Tone;
+use crate::term::{TermInput, TermStyle, Terminal};
+use crate::video::VideoWriter;
+#[cfg(feature = "sdl")]
+use sdl2::event::{Event, WindowEvent};
+#[cfg(feature = "sdl")]
+use sdl2::keyboard::{Keycode, Scancode};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/**
+ * Emulator hotkeys
+ *   F2: next palette
+ *   F4: cycle phosphor persistence (off, fade, blend)
+ *   F5: save state to the current slot
+ *   F6 / F7: previous / next slot (0 ~ 9)
+ *   F8: mute / unmute
+ *   F9: load state from the current slot
+ *   F10: start / stop recording a GIF
+ *   F12: take a screenshot
+ *   Pause or P: pause / resume, unless P is bound to a CHIP-8 key
+ *
+ * F1 (frame counter), F3 (CRT filter) and F11 (fullscreen) only concern
+ * the window, so the SDL frontend handles them itself.
+ */
+enum Hotkey {
+    NextPalette,
+    NextPersistence,
+    SaveState,
+    PrevSlot,
+    NextSlot,
+    Mute,
+    LoadState,
+    RecordGif,
+    Screenshot,
+    Pause,
+}
+
+impl Hotkey {
+    fn from_function_key(n: u8) -> Option<Self> {
+        match n {
+            2 => Some(Hotkey::NextPalette),
+            4 => Some(Hotkey::NextPersistence),
+            5 => Some(Hotkey::SaveState),
+            6 => Some(Hotkey::PrevSlot),
+            7 => Some(Hotkey::NextSlot),
+            8 => Some(Hotkey::Mute),
+            9 => Some(Hotkey::LoadState),
+            10 => Some(Hotkey::RecordGif),
+            12 => Some(Hotkey::Screenshot),
+            _ => None,
+        }
+    }
+}
+
+// frames a pixel takes to fade out, unless configured otherwise
+const DEFAULT_DECAY_FRAMES: u8 = 4;
 
 pub struct Emulator {
     cpu: Cpu,
-    graphics: Graphics,
     fps: u32,
+
+    // identifies the loaded ROM for its saved data
+    rom_hash: String,
+
+    // file name of the loaded ROM without extension, for screenshots
+    rom_name: String,
+
+    // where the screenshot and recording hotkeys save to, and how much
+    // they enlarge
+    screenshot_dir: PathBuf,
+    screenshot_scale: usize,
+
+    // save state slot used by the save / load hotkeys
+    state_slot: u8,
+
+    // messages for the user, shown by the frontend
+    messages: Vec<String>,
+
+    // colors of the display
+    palette: Palette,
+
+    // afterglow of pixels that were turned off
+    phosphor: Phosphor,
+
+    // fade length used when persistence is turned on with the hotkey
+    decay_frames: u8,
+
+    // stop running instructions and timers
+    paused: bool,
+
+    // how the interactive frontends keep to real time
+    pacing: Pacing,
+
+    // which host keys press which CHIP-8 keys
+    keymap: Keymap,
+
+    // autofire of the turbo keys held down
+    turbo: Vec<Turbo>,
+
+    // cycle the macro playing ends at. macros do not overlap, as their
+    // presses and releases would mix up
+    macro_end: u64,
+
+    // where sound goes, each with a beeper making sound at its sample rate
+    audio: Vec<(Box<dyn AudioSink>, Beeper)>,
+    tone: Tone,
+    muted: bool,
+
+    // when the last slice of instructions ran, to place input in time
+    last_slice: Instant,
+
+    // GIF being recorded, if any
+    recorder: Option<GifRecorder>,
+
+    // raw video being written, if any
+    video: Option<VideoWriter>,
 }
 
 impl Emulator {
-    pub fn new() -> Result<Self, String> {
+    pub fn new() -> Self {
         let cpu = Cpu::new();
-        let graphics = Graphics::new()?;
 
-        Ok(Emulator {
+        let mut emu = Emulator {
             cpu,
-            graphics,
-            fps: 1000, // 1000 fps by default
-        })
+            fps: FPS,
+            rom_hash: String::new(),
+            rom_name: String::new(),
+            screenshot_dir: PathBuf::from("."),
+            screenshot_scale: 1,
+            state_slot: 0,
+            messages: Vec::new(),
+            palette: Palette::default(),
+            phosphor: Phosphor::new(Persistence::Off, 64 * 32),
+            decay_frames: DEFAULT_DECAY_FRAMES,
+            paused: false,
+            pacing: Pacing::Frame,
+            keymap: Keymap::default(),
+            turbo: Vec::new(),
+            macro_end: 0,
+            audio: Vec::new(),
+            tone: Tone::default(),
+            muted: false,
+            last_slice: Instant::now(),
+            recorder: None,
+            video: None,
+        };
+
+        // every press lasts at least a frame, which is how often most
+        // programs look at the keys
+        emu.cpu.input.min_hold = u64::from(emu.fps / 60);
+        emu
+    }
+
+    // use a different source of random bytes for CXNN,
+    // e.g. a seeded generator so that runs are reproducible
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.cpu.set_rng(rng);
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.cpu.redraw = true;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+    }
+
+    // takes effect for the next ROM loaded, which may add its own bindings
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    pub fn set_persistence(&mut self, persistence: Persistence) {
+        if let Persistence::Decay(frames) = persistence {
+            self.decay_frames = frames;
+        }
+        self.phosphor.persistence = persistence;
+        self.cpu.redraw = true;
+    }
+
+    // off -> fade -> blend -> off
+    fn next_persistence(&self) -> Persistence {
+        match self.phosphor.persistence {
+            Persistence::Off => Persistence::Decay(self.decay_frames),
+            Persistence::Decay(_) => Persistence::Blend,
+            Persistence::Blend => Persistence::Off,
+        }
+    }
+
+    // brightness of each pixel, as it should be drawn
+    fn shades(&self) -> Vec<u8> {
+        self.phosphor.shade(&self.cpu.gfx)
+    }
+
+    // the display as it is shown, in the colors of the palette
+    pub fn frame(&self) -> Frame {
+        Frame::render(&self.shades(), self.cpu.display_width(), &self.palette)
+    }
+
+    /**
+     * save the display to a PNG file.
+     * every pixel becomes `scale` x `scale` pixels in the image.
+     */
+    pub fn screenshot(&self, path: &Path, scale: usize) -> Result<(), String> {
+        screenshot::save_png(&self.frame(), scale, path)
+    }
+
+    // where the screenshot hotkey saves to, and how much it enlarges
+    pub fn set_screenshot_options(&mut self, dir: PathBuf, scale: usize) {
+        self.screenshot_dir = dir;
+        self.screenshot_scale = scale;
+    }
+
+    /**
+     * a file in the screenshot directory named after the ROM and the
+     * current time, e.g. "pong-20240101-120000.png"
+     */
+    fn capture_path(&self, extension: &str) -> PathBuf {
+        let name = format!("{}-{}", self.rom_name, storage::timestamp());
+
+        // don't overwrite files saved within the same second
+        let mut path = self.screenshot_dir.join(format!("{}.{}", name, extension));
+        let mut n = 1;
+        while path.exists() {
+            n += 1;
+            path = self
+                .screenshot_dir
+                .join(format!("{}-{}.{}", name, n, extension));
+        }
+
+        path
+    }
+
+    fn take_screenshot(&mut self) {
+        let path = self.capture_path("png");
+
+        match self.screenshot(&path, self.screenshot_scale) {
+            Ok(()) => self.notify(format!("Screenshot saved to {}", path.display())),
+            Err(e) => self.notify(format!("Could not save screenshot: {}", e)),
+        }
+    }
+
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+    }
+
+    // what the beep sounds like
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+        for (_, beeper) in self.audio.iter_mut() {
+            beeper.synth.tone = tone;
+        }
+    }
+
+    /**
+     * send the sound of the emulated machine to `sink`, e.g. the sound
+     * card or a file, in addition to the sinks added before
+     */
+    pub fn add_audio(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio.push((sink, Beeper::new(self.tone)));
+    }
+
+    // sound queued for the sound card, if there is one
+    fn audio_buffered(&self) -> Option<Duration> {
+        self.audio
+            .iter()
+            .filter_map(|(sink, _)| sink.buffered())
+            .min()
+    }
+
+    // complete and remove all sinks
+    fn finish_audio(&mut self) {
+        let mut errors = Vec::new();
+        for (mut sink, mut beeper) in self.audio.drain(..) {
+            beeper.flush(sink.as_mut());
+            if let Err(e) = sink.finish() {
+                errors.push(e);
+            }
+        }
+
+        for e in errors {
+            self.notify(format!("Could not write audio: {}", e));
+        }
+    }
+
+    /**
+     * record the display into a GIF, one frame every 1/60 s, until
+     * `stop_recording`. uses the screenshot scale.
+     */
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), String> {
+        self.stop_recording();
+
+        let width = self.cpu.display_width();
+        let height = self.cpu.gfx.len() / width;
+        let recorder = GifRecorder::new(path, width, height, self.screenshot_scale, &self.palette)?;
+        self.recorder = Some(recorder);
+
+        Ok(())
+    }
+
+    // finish the GIF being recorded, if any
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            let path = recorder.path.clone();
+            match recorder.finish() {
+                Ok(()) => self.notify(format!("GIF saved to {}", path.display())),
+                Err(e) => self.notify(format!("Could not save GIF: {}", e)),
+            }
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if self.recorder.is_some() {
+            self.stop_recording();
+            return;
+        }
+
+        let path = self.capture_path("gif");
+        match self.start_recording(&path) {
+            Ok(()) => self.notify(format!("Recording GIF to {}", path.display())),
+            Err(e) => self.notify(format!("Could not record GIF: {}", e)),
+        }
+    }
+
+    /**
+     * write the display into a y4m video at `path` ("-" for stdout),
+     * one frame every 1/60 s of emulated time. uses the screenshot scale.
+     */
+    pub fn start_video(&mut self, path: &str) -> Result<(), String> {
+        self.stop_video();
+        self.video = Some(VideoWriter::create(path, self.screenshot_scale)?);
+        Ok(())
+    }
+
+    pub fn stop_video(&mut self) {
+        if let Some(video) = self.video.take() {
+            if let Err(e) = video.finish() {
+                self.notify(format!("Could not write video: {}", e));
+            }
+        }
+    }
+
+    // add the display to the GIF and video being recorded
+    fn capture_frame(&mut self) {
+        if self.recorder.is_some() {
+            let shades = self.shades();
+            let result = match self.recorder.as_mut() {
+                Some(recorder) => recorder.frame(&shades, &self.palette),
+                None => Ok(()),
+            };
+
+            if let Err(e) = result {
+                self.recorder = None;
+                self.notify(format!("Stopped recording GIF: {}", e));
+            }
+        }
+
+        if self.video.is_some() {
+            let frame = self.frame();
+            let result = match self.video.as_mut() {
+                Some(video) => video.frame(&frame),
+                None => Ok(()),
+            };
+
+            if let Err(e) = result {
+                self.video = None;
+                self.notify(format!("Stopped writing video: {}", e));
+            }
+        }
+    }
+
+    /**
+     * advance the timers, and the phosphor once per frame.
+     * returns true at the end of a frame (every 1/60 s).
+     */
+    fn update_timers(&mut self) -> bool {
+        if !self.cpu.update_timers(self.fps) {
+            return false;
+        }
+
+        self.phosphor.frame(&self.cpu.gfx);
+        self.save_flags();
+
+        for (sink, beeper) in self.audio.iter_mut() {
+            beeper.flush(sink.as_mut());
+        }
+
+        // keep drawing while pixels fade out
+        if self.phosphor.is_on() {
+            self.cpu.redraw = true;
+        }
+
+        self.capture_frame();
+
+        true
     }
 
     pub fn load_rom(&mut self, filename: &str) {
         let buffer = std::fs::read(filename).unwrap();
         self.cpu.load_rom(&buffer);
+        self.rom_hash = storage::rom_hash(&buffer);
+        self.rom_name = Path::new(filename)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        // restore the RPL flags of the previous session, like the HP48 does
+        match storage::load_flags(&self.rom_hash) {
+            Ok(Some(flags)) => self.cpu.set_rpl_flags(&flags),
+            Ok(None) => (),
+            Err(e) => self.notify(format!("Could not load RPL flags: {}", e)),
+        }
+
+        // bindings for this ROM only, on top of the keymap
+        match storage::rom_keymap_path(&self.rom_name) {
+            Ok(path) if path.exists() => match Keymap::read(&path, &self.keymap) {
+                Ok(keymap) => self.keymap = keymap,
+                Err(e) => self.notify(format!("Could not load keymap: {}", e)),
+            },
+            Ok(_) => (),
+            Err(e) => self.notify(format!("Could not load keymap: {}", e)),
+        }
+    }
+
+    // persist the RPL flags of the loaded ROM, if they were written since
+    // the last time. programs may write them every instruction, so this is
+    // done once a frame, and on quit
+    fn save_flags(&mut self) {
+        if !self.cpu.rpl_changed {
+            return;
+        }
+        self.cpu.rpl_changed = false;
+
+        if let Err(e) = storage::save_flags(&self.rom_hash, self.cpu.rpl_flags()) {
+            self.notify(format!("Could not save RPL flags: {}", e));
+        }
+    }
+
+    pub fn save_state(&self, path: &Path) -> Result<(), String> {
+        self.cpu.save_state().write(path)
+    }
+
+    pub fn load_state(&mut self, path: &Path) -> Result<(), String> {
+        let state = State::read(path)?;
+        self.cpu.load_state(&state);
+        Ok(())
+    }
+
+    // is there an autosave of the loaded ROM to resume from?
+    pub fn has_autosave(&self) -> bool {
+        storage::autosave_path(&self.rom_hash)
+            .map(|path| path.exists())
+            .unwrap_or(false)
+    }
+
+    // continue where the last session of the loaded ROM was closed
+    pub fn resume(&mut self) -> Result<(), String> {
+        let path = storage::autosave_path(&self.rom_hash)?;
+        self.load_state(&path)
+    }
+
+    // delete the autosave of the loaded ROM, e.g. one that does not load
+    pub fn discard_autosave(&self) -> Result<(), String> {
+        let path = storage::autosave_path(&self.rom_hash)?;
+        std::fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn autosave(&mut self) {
+        let result = storage::autosave_path(&self.rom_hash).and_then(|path| self.save_state(&path));
+
+        if let Err(e) = result {
+            self.notify(format!("Could not write autosave: {}", e));
+        }
+    }
+
+    fn save_slot(&mut self) {
+        let result = storage::state_path(&self.rom_hash, self.state_slot)
+            .and_then(|path| self.save_state(&path).map(|_| path));
+
+        match result {
+            Ok(path) => self.notify(format!("State saved to {}", path.display())),
+            Err(e) => self.notify(format!("Could not save state: {}", e)),
+        }
+    }
+
+    fn load_slot(&mut self) {
+        let result = storage::state_path(&self.rom_hash, self.state_slot)
+            .and_then(|path| self.load_state(&path).map(|_| path));
+
+        match result {
+            Ok(path) => self.notify(format!("State loaded from {}", path.display())),
+            Err(e) => self.notify(format!("Could not load state: {}", e)),
+        }
+    }
+
+    fn notify(&mut self, message: String) {
+        self.messages.push(message);
+    }
+
+    // on stderr, so they don't end up in a video written to stdout
+    fn print_messages(&mut self) {
+        for message in self.messages.drain(..) {
+            eprintln!("{}", message);
+        }
+    }
+
+    // finish everything being recorded before quitting
+    fn shut_down(&mut self) {
+        self.save_flags();
+        self.stop_recording();
+        self.stop_video();
+        self.finish_audio();
+        self.print_messages();
+    }
+
+    fn handle_hotkey(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::NextPalette => {
+                self.set_palette(self.palette.next_preset());
+                self.notify(format!("Palette {}", self.palette.name));
+            }
+            Hotkey::NextPersistence => {
+                self.set_persistence(self.next_persistence());
+                self.notify(format!("Persistence {}", self.phosphor.persistence.name()));
+            }
+            Hotkey::SaveState => self.save_slot(),
+            Hotkey::PrevSlot => {
+                self.state_slot = (self.state_slot + 9) % 10;
+                self.notify(format!("State slot {}", self.state_slot));
+            }
+            Hotkey::NextSlot => {
+                self.state_slot = (self.state_slot + 1) % 10;
+                self.notify(format!("State slot {}", self.state_slot));
+            }
+            Hotkey::Mute => {
+                self.muted = !self.muted;
+                self.notify(String::from(if self.muted {
+                    "Sound off"
+                } else {
+                    "Sound on"
+                }));
+            }
+            Hotkey::LoadState => self.load_slot(),
+            Hotkey::RecordGif => self.toggle_recording(),
+            Hotkey::Screenshot => self.take_screenshot(),
+            Hotkey::Pause => {
+                self.paused = !self.paused;
+                self.notify(String::from(if self.paused { "Paused" } else { "Resumed" }));
+            }
+        }
+    }
+
+    // number of instructions run in a frame
+    fn frame_cycles(&self) -> u64 {
+        u64::from(self.fps / 60)
+    }
+
+    /**
+     * the cycle input from `at` takes effect at.
+     * input from since the last slice is spread over the next slice the way
+     * it was spread in time, so quick presses land at the right cycles.
+     */
+    fn input_cycle(&self, at: Instant) -> u64 {
+        let since = at.saturating_duration_since(self.last_slice);
+        let offset = (since.as_secs_f64() * f64::from(self.fps)) as u64;
+
+        self.cpu.cycles() + offset.min(self.frame_cycles().saturating_sub(1))
+    }
+
+    /**
+     * press or release a CHIP-8 key for the host input `holder`, as of `at`.
+     * a key held by several inputs stays pressed until all let go.
+     */
+    fn set_key(&mut self, holder: &str, index: usize, pressed: bool, at: Instant) {
+        let cycle = self.input_cycle(at);
+        self.cpu.input.hold(holder, index, pressed, cycle);
+    }
+
+    /**
+     * a host key bound in the keymap went down or up, as of `at`.
+     * turbo keys and macros go through the input queue like plain keys.
+     */
+    fn host_key(&mut self, name: &str, pressed: bool, at: Instant) {
+        let cycle = self.input_cycle(at);
+
+        match self.keymap.action(name).cloned() {
+            Some(Action::Turbo { key, rate }) => {
+                let name = name.to_lowercase();
+                let running = self.turbo.iter().position(|turbo| turbo.host_key == name);
+
+                match (running, pressed) {
+                    (None, true) => {
+                        let turbo = Turbo::new(&name, key, rate, self.fps, cycle);
+                        self.turbo.push(turbo);
+                    }
+                    (Some(i), false) => self.turbo.remove(i).stop(&mut self.cpu.input, cycle),
+                    _ => (),
+                }
+            }
+            Some(Action::Macro(steps)) => {
+                if pressed && cycle >= self.macro_end {
+                    let frame = self.frame_cycles();
+                    let holder = format!("macro {}", name.to_lowercase());
+                    self.macro_end = macros::play(&holder, &steps, &mut self.cpu.input, cycle, frame);
+                }
+            }
+            None => {
+                if let Some(index) = self.keymap.index(name) {
+                    self.set_key(&format!("key {}", name.to_lowercase()), index, pressed, at);
+                }
+            }
+        }
+    }
+
+    // run opcode
+    fn step(&mut self) {
+        self.cpu.execute_inst();
+
+        let beeping = self.cpu.sound_timer > 0;
+        let pattern = self.cpu.audio_pattern();
+        for (sink, beeper) in self.audio.iter_mut() {
+            beeper.synth.pattern = pattern;
+            let muted = self.muted && sink.is_live();
+            beeper.run(beeping && !muted, self.fps, sink.sample_rate());
+        }
+    }
+
+    /**
+     * run a frame (until the timers tick), or a single instruction.
+     * returns the number of instructions run and of frames completed.
+     */
+    fn run_slice(&mut self, whole_frame: bool) -> (u64, u32) {
+        let mut instructions = 0;
+        self.last_slice = Instant::now();
+
+        let until = self.cpu.cycles() + self.frame_cycles();
+        for turbo in self.turbo.iter_mut() {
+            turbo.feed(&mut self.cpu.input, until);
+        }
+
+        loop {
+            self.step();
+            instructions += 1;
+
+            if self.update_timers() {
+                return (instructions, 1);
+            }
+            if !whole_frame {
+                return (instructions, 0);
+            }
+        }
     }
 
     /**
      * Main loop of emulator
      *
      * 1. listen to & handle events
-     * 2. run opcode
+     * 2. run opcodes & update timers, as much as the pacing calls for
      * 3. update screen
-     * 4. update timers
+     * 4. wait until the emulator has to run again
+     *
+     * scale: initial size of a CHIP-8 pixel in the window
+     * scaling: how the display is fitted into the window
+     * filter: post-processing, toggled with F3
      */
-    pub fn run_loop(&mut self) -> Result<(), String> {
-        let ctx = &mut self.graphics.ctx;
-        let mut event_pump = ctx.event_pump()?;
+    #[cfg(feature = "sdl")]
+    pub fn run_loop(&mut self, scale: f32, scaling: Scaling, filter: Filter) -> Result<(), String> {
+        let mut graphics = Graphics::new(scale, scaling, filter)?;
+        let mut event_pump = graphics.ctx.event_pump()?;
+        let timer = graphics.ctx.timer()?;
+
+        // carry on without sound if there is no sound card
+        match SdlAudio::new(&graphics.ctx) {
+            Ok(audio) => self.add_audio(Box::new(audio)),
+            Err(e) => self.notify(format!("No sound: {}", e)),
+        }
+
+        // carry on with the keyboard if controllers are not supported
+        let mut gamepads = match Gamepads::new(&graphics.ctx) {
+            Ok(gamepads) => Some(gamepads),
+            Err(e) => {
+                self.notify(format!("No controllers: {}", e));
+                None
+            }
+        };
+
+        let mut pacer = Pacer::new(self.pacing, self.fps);
 
         'running: loop {
             // 1. listen to & handle events
+            let now = Instant::now();
+            let ticks = timer.ticks();
+
             for event in event_pump.poll_iter() {
+                // when the event happened. SDL stamps events in ms since it started
+                let age =
+                    Duration::from_millis(u64::from(ticks.saturating_sub(event.get_timestamp())));
+                let at = now.checked_sub(age).unwrap_or(now);
+
+                if let Some(gamepads) = gamepads.as_mut() {
+                    match gamepads.handle(&event) {
+                        Ok(inputs) => self.handle_pad_inputs(inputs, at),
+                        Err(e) => self.notify(format!("Could not open controller: {}", e)),
+                    }
+                }
+
                 match event {
-                    Event::Quit { .. } => break 'running,
+                    Event::Quit { .. } => {
+                        self.autosave();
+                        break 'running;
+                    }
+                    Event::Window {
+                        win_event: WindowEvent::SizeChanged(..),
+                        ..
+                    }
+                    | Event::Window {
+                        win_event: WindowEvent::Exposed,
+                        ..
+                    } => graphics.invalidate(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F1),
+                        repeat: false,
+                        ..
+                    } => graphics.osd.show_stats = !graphics.osd.show_stats,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F3),
+                        repeat: false,
+                        ..
+                    } => {
+                        let on = graphics.toggle_filter();
+                        self.notify(format!("Filter {}", if on { "on" } else { "off" }));
+                    }
                     Event::KeyDown {
-                        keycode: Some(keycode),
+                        keycode: Some(Keycode::F11),
+                        repeat: false,
                         ..
-                    } => self.handle_keydown(keycode),
+                    } => graphics.toggle_fullscreen()?,
+                    Event::KeyDown {
+                        keycode,
+                        scancode,
+                        repeat,
+                        ..
+                    } => self.handle_keydown(keycode, scancode, repeat, at),
                     Event::KeyUp {
-                        keycode: Some(keycode),
+                        scancode: Some(scancode),
                         ..
-                    } => self.handle_keyup(keycode),
+                    } => self.handle_keyup(scancode, at),
                     _ => (),
                 }
             }
 
-            // 2. run opcode
-            self.cpu.execute_inst();
+            for message in self.messages.iter() {
+                graphics.osd.message(message.clone());
+            }
+            self.print_messages();
+            graphics.osd.paused = self.paused;
+
+            // 2. run opcodes & update timers
+            if !self.paused && pacer.due(self.audio_buffered()) {
+                let (instructions, frames) = self.run_slice(pacer.runs_frames());
+                graphics.osd.count(instructions, frames);
+            }
 
             // 3. update screen
             if self.cpu.redraw {
                 self.cpu.redraw = false;
-                self.graphics.draw(&self.cpu.gfx)?;
+                graphics.draw(&self.shades(), self.cpu.display_width(), &self.palette);
             }
+            graphics.present()?;
 
-            // 4. update timers
-            self.cpu.update_timers(self.fps);
+            // 4. wait
+            pacer.wait(self.audio_buffered());
+        }
+
+        self.shut_down();
 
-            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / self.fps));
+        Ok(())
+    }
+
+    /**
+     * Same as `run_loop`, but drawing into the terminal.
+     * The terminal is repainted at most 60 times a second, as writing
+     * to it is much slower than drawing with SDL.
+     */
+    pub fn run_terminal_loop(&mut self, style: TermStyle) -> Result<(), String> {
+        let mut terminal = Terminal::new(style)?;
+        let frame = Duration::from_secs(1) / 60;
+        let mut last_draw: Option<Instant> = None;
+        let mut pacer = Pacer::new(self.pacing, self.fps);
+
+        'running: loop {
+            // 1. listen to & handle events
+            for input in terminal.poll_input(&self.keymap)? {
+                match input {
+                    TermInput::Quit => {
+                        self.autosave();
+                        break 'running;
+                    }
+                    TermInput::Key(name, pressed) => self.host_key(&name, pressed, Instant::now()),
+                    TermInput::FunctionKey(n) => {
+                        if let Some(hotkey) = Hotkey::from_function_key(n) {
+                            self.handle_hotkey(hotkey);
+                        }
+                    }
+                    TermInput::Pause => self.handle_hotkey(Hotkey::Pause),
+                }
+            }
+
+            // only the latest message fits on the status line
+            if let Some(message) = self.messages.pop() {
+                self.messages.clear();
+                terminal.status(&message)?;
+            }
+
+            // 2. run opcodes & update timers
+            if !self.paused && pacer.due(self.audio_buffered()) {
+                self.run_slice(pacer.runs_frames());
+            }
+
+            // 3. update screen
+            let due = last_draw.map_or(true, |last| last.elapsed() >= frame);
+            if self.cpu.redraw && due {
+                self.cpu.redraw = false;
+                terminal.draw(&self.shades(), self.cpu.display_width(), &self.palette)?;
+                last_draw = Some(Instant::now());
+            }
+
+            // 4. wait
+            pacer.wait(self.audio_buffered());
+        }
+
+        drop(terminal);
+        self.shut_down();
+
+        Ok(())
+    }
+
+    /**
+     * Run without a display or input for `frames` frames (1/60 s each),
+     * as fast as possible, e.g. to record a GIF with `start_recording`.
+     */
+    pub fn run_headless(&mut self, frames: u64) -> Result<(), String> {
+        for _ in 0..frames {
+            self.run_slice(true);
         }
 
+        self.shut_down();
+
         Ok(())
     }
 
-    fn handle_keydown(&mut self, keycode: Keycode) {
-        if let Some(index) = lib::keycode_to_index(keycode) {
-            self.cpu.keyboard[index] = true;
+    /**
+     * hotkeys go by the label of the key (keycode), CHIP-8 keys by its
+     * position (scancode), so that keymaps work with any keyboard layout
+     */
+    #[cfg(feature = "sdl")]
+    fn handle_keydown(
+        &mut self,
+        keycode: Option<Keycode>,
+        scancode: Option<Scancode>,
+        repeat: bool,
+        at: Instant,
+    ) {
+        // held keys stay pressed, and hotkeys fire once
+        if repeat {
+            return;
+        }
+
+        let name = scancode.map(|scancode| scancode.name());
+        let bound = name.filter(|name| self.keymap.binds(name));
+        if let Some(name) = bound {
+            self.host_key(name, true, at);
+        }
+
+        let keycode = match keycode {
+            Some(keycode) => keycode,
+            None => return,
+        };
+
+        if let Some(hotkey) = function_key_number(keycode).and_then(Hotkey::from_function_key) {
+            self.handle_hotkey(hotkey);
+        }
+
+        if keycode == Keycode::Pause || (keycode == Keycode::P && bound.is_none()) {
+            self.handle_hotkey(Hotkey::Pause);
         }
     }
 
-    fn handle_keyup(&mut self, keycode: Keycode) {
-        if let Some(index) = lib::keycode_to_index(keycode) {
-            self.cpu.keyboard[index as usize] = false;
+    #[cfg(feature = "sdl")]
+    fn handle_pad_inputs(&mut self, inputs: Vec<PadInput>, at: Instant) {
+        for input in inputs {
+            match input {
+                PadInput::Connected(name) => self.notify(format!("{} connected", name)),
+                PadInput::Disconnected(name) => self.notify(format!("{} disconnected", name)),
+                PadInput::Button(id, name, pressed) => {
+                    if let Some(index) = self.keymap.button_index(&name) {
+                        self.set_key(&format!("pad {} {}", id, name), index, pressed, at);
+                    }
+                }
+            }
         }
     }
+
+    #[cfg(feature = "sdl")]
+    fn handle_keyup(&mut self, scancode: Scancode, at: Instant) {
+        self.host_key(scancode.name(), false, at);
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn function_key_number(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        Keycode::F10 => Some(10),
+        Keycode::F11 => Some(11),
+        Keycode::F12 => Some(12),
+        _ => None,
+    }
 }
@@ -0,0 +1,193 @@
+use crate::frame::Frame;
+use crate::palette::Rgb;
+
+// how bright the darkened parts of scanlines and the pixel grid stay
+const SCANLINE_BRIGHTNESS: f32 = 0.55;
+const GRID_BRIGHTNESS: f32 = 0.4;
+
+// how much light spills over from neighbouring pixels
+const BLOOM_STRENGTH: f32 = 0.35;
+
+// how far the corners are bent outwards, relative to the screen size
+const CURVATURE: f32 = 0.04;
+
+/**
+ * Post-processing that makes the display look like a CRT
+ *
+ * scanlines: darken the bottom of every row of pixels
+ * grid:      thin dark gaps between pixels
+ * bloom:     lit pixels glow into their surroundings
+ * curvature: bend the screen like the glass of a tube, with darker corners
+ *
+ * Everything is computed on the CPU on the enlarged display, so no
+ * graphics card is needed.
+ */
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Filter {
+    pub scanlines: bool,
+    pub grid: bool,
+    pub bloom: bool,
+    pub curvature: bool,
+}
+
+impl Filter {
+    // all effects at once
+    pub const CRT: Filter = Filter {
+        scanlines: true,
+        grid: true,
+        bloom: true,
+        curvature: true,
+    };
+
+    /**
+     * accepted values are none, crt (everything) or a comma separated
+     * list of effects, e.g. "scanlines,bloom"
+     */
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "none" => return Ok(Filter::default()),
+            "crt" => return Ok(Filter::CRT),
+            _ => (),
+        }
+
+        let mut filter = Filter::default();
+        for effect in value.split(',') {
+            match effect.trim() {
+                "scanlines" => filter.scanlines = true,
+                "grid" => filter.grid = true,
+                "bloom" => filter.bloom = true,
+                "curvature" => filter.curvature = true,
+                effect => return Err(format!(
+                    "Unknown filter {}, expected none, crt, scanlines, grid, bloom or curvature",
+                    effect
+                )),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    pub fn is_on(&self) -> bool {
+        *self != Filter::default()
+    }
+
+    /**
+     * enlarge `frame` `factor` times and apply the effects.
+     * effects need room, so the bigger the factor, the better they look.
+     */
+    pub fn apply(&self, frame: &Frame, factor: usize) -> Frame {
+        let factor = factor.max(1);
+        let width = frame.width * factor;
+        let height = frame.height * factor;
+
+        let glow = if self.bloom {
+            bloom(frame)
+        } else {
+            vec![[0.0; 3]; frame.pixels.len()]
+        };
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(self.pixel(frame, &glow, factor, x, y));
+            }
+        }
+
+        Frame {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // color of the pixel at (x, y) of the enlarged frame
+    fn pixel(&self, frame: &Frame, glow: &[[f32; 3]], factor: usize, x: usize, y: usize) -> Rgb {
+        let width = (frame.width * factor) as f32;
+        let height = (frame.height * factor) as f32;
+
+        // position on the screen, 0.0 ~ 1.0
+        let mut u = (x as f32 + 0.5) / width;
+        let mut v = (y as f32 + 0.5) / height;
+        let mut vignette = 1.0;
+
+        if self.curvature {
+            let (cu, cv) = (u * 2.0 - 1.0, v * 2.0 - 1.0);
+            u = (cu * (1.0 + CURVATURE * cv * cv) + 1.0) / 2.0;
+            v = (cv * (1.0 + CURVATURE * cu * cu) + 1.0) / 2.0;
+
+            // beyond the edge of the bent screen
+            if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                return Rgb(0, 0, 0);
+            }
+
+            vignette = (16.0 * u * (1.0 - u) * v * (1.0 - v)).powf(0.15);
+        }
+
+        // which pixel of the frame, and where inside of it
+        let sx = ((u * width) as usize).min(frame.width * factor - 1);
+        let sy = ((v * height) as usize).min(frame.height * factor - 1);
+        let (cell_x, cell_y) = (sx % factor, sy % factor);
+        let index = (sy / factor) * frame.width + sx / factor;
+
+        let mut brightness = vignette;
+        if self.scanlines && factor >= 2 && (cell_y as f32 + 0.5) / factor as f32 > 0.66 {
+            brightness *= SCANLINE_BRIGHTNESS;
+        }
+        if self.grid && factor >= 3 && (cell_x == factor - 1 || cell_y == factor - 1) {
+            brightness *= GRID_BRIGHTNESS;
+        }
+
+        // the glow also lights up the dark lines between pixels
+        let base = frame.pixels[index];
+        let [glow_r, glow_g, glow_b] = glow[index];
+        let channel = |base: u8, glow: f32| {
+            (base as f32 * brightness + glow * vignette)
+                .round()
+                .min(255.0) as u8
+        };
+
+        Rgb(
+            channel(base.0, glow_r),
+            channel(base.1, glow_g),
+            channel(base.2, glow_b),
+        )
+    }
+}
+
+// light each pixel of `frame` receives from itself and its neighbours
+fn bloom(frame: &Frame) -> Vec<[f32; 3]> {
+    const WEIGHTS: [[f32; 3]; 3] = [[1.0, 2.0, 1.0], [2.0, 4.0, 2.0], [1.0, 2.0, 1.0]];
+
+    let (width, height) = (frame.width as i32, frame.height as i32);
+    let mut glow = Vec::with_capacity(frame.pixels.len());
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 3];
+            let mut total = 0.0;
+
+            for (dy, row) in WEIGHTS.iter().enumerate() {
+                for (dx, weight) in row.iter().enumerate() {
+                    let (nx, ny) = (x + dx as i32 - 1, y + dy as i32 - 1);
+                    if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                        continue;
+                    }
+
+                    let pixel = frame.pixels[(ny * width + nx) as usize];
+                    sum[0] += pixel.0 as f32 * weight;
+                    sum[1] += pixel.1 as f32 * weight;
+                    sum[2] += pixel.2 as f32 * weight;
+                    total += weight;
+                }
+            }
+
+            glow.push([
+                sum[0] / total * BLOOM_STRENGTH,
+                sum[1] / total * BLOOM_STRENGTH,
+                sum[2] / total * BLOOM_STRENGTH,
+            ]);
+        }
+    }
+
+    glow
+}
@@ -0,0 +1,70 @@
+use crate::palette::{self, Palette, Rgb};
+
+/**
+ * The display as colors, ready to be shown or saved.
+ * Pixels are stored row by row, starting at the top left.
+ */
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl Frame {
+    /**
+     * color the display with `palette`.
+     * `shades` is the brightness of each pixel, 0 (off) ~ 255 (on), and the
+     * display is `width` pixels wide.
+     */
+    pub fn render(shades: &[u8], width: usize, palette: &Palette) -> Self {
+        let background = palette.background();
+        let foreground = palette.foreground();
+
+        Frame {
+            width,
+            height: shades.len() / width,
+            pixels: shades
+                .iter()
+                .map(|&shade| palette::mix(background, foreground, shade as u32, 0xFF))
+                .collect(),
+        }
+    }
+
+    // 3 bytes (red, green, blue) per pixel
+    pub fn to_rgb24(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in self.pixels.iter() {
+            bytes.extend_from_slice(&[pixel.0, pixel.1, pixel.2]);
+        }
+        bytes
+    }
+
+    // every pixel becomes a square of `factor` x `factor` pixels
+    pub fn scale(&self, factor: usize) -> Self {
+        let factor = factor.max(1);
+
+        Frame {
+            width: self.width * factor,
+            height: self.height * factor,
+            pixels: upscale(&self.pixels, self.width, factor),
+        }
+    }
+}
+
+/**
+ * enlarge an image `width` pixels wide, turning every pixel into a square
+ * of `factor` x `factor` pixels
+ */
+pub fn upscale<T: Copy>(pixels: &[T], width: usize, factor: usize) -> Vec<T> {
+    let mut scaled = Vec::with_capacity(pixels.len() * factor * factor);
+
+    for row in pixels.chunks(width) {
+        for _ in 0..factor {
+            for &pixel in row {
+                scaled.extend(std::iter::repeat(pixel).take(factor));
+            }
+        }
+    }
+
+    scaled
+}
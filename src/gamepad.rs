@@ -0,0 +1,119 @@
+use sdl2::controller::{Axis, GameController};
+use sdl2::event::Event;
+use sdl2::{GameControllerSubsystem, Sdl};
+
+// how far a stick or trigger has to be pushed to count as a press, of 32767
+const THRESHOLD: i16 = 16384;
+
+// what happened on a controller, for the emulator
+pub enum PadInput {
+    // name of the controller
+    Connected(String),
+    Disconnected(String),
+    // controller id, button or direction named like in a keymap,
+    // pressed or released
+    Button(u32, String, bool),
+}
+
+/**
+ * Game controllers, opened and closed as they are plugged in and out.
+ * Sticks and triggers act like buttons for each direction, e.g. "leftx-"
+ * is pressed while the left stick is pushed to the left.
+ */
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+
+    // controller id and name of every button or direction held down,
+    // to release them when their controller goes away
+    held: Vec<(u32, String)>,
+}
+
+impl Gamepads {
+    // controllers plugged in already are reported by added events as well
+    pub fn new(ctx: &Sdl) -> Result<Self, String> {
+        Ok(Gamepads {
+            subsystem: ctx.game_controller()?,
+            controllers: Vec::new(),
+            held: Vec::new(),
+        })
+    }
+
+    // translate a controller event. other events give nothing.
+    pub fn handle(&mut self, event: &Event) -> Result<Vec<PadInput>, String> {
+        let inputs = match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let controller = self.subsystem.open(which).map_err(|e| e.to_string())?;
+                let name = controller.name();
+                self.controllers.push(controller);
+                vec![PadInput::Connected(name)]
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let mut inputs = self.release_all(which);
+                if let Some(i) = self
+                    .controllers
+                    .iter()
+                    .position(|c| c.instance_id() == which)
+                {
+                    let controller = self.controllers.remove(i);
+                    inputs.push(PadInput::Disconnected(controller.name()));
+                }
+                inputs
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.set(which, button.string(), true).into_iter().collect()
+            }
+            Event::ControllerButtonUp { which, button, .. } => self
+                .set(which, button.string(), false)
+                .into_iter()
+                .collect(),
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => self.axis(which, axis, value),
+            _ => Vec::new(),
+        };
+
+        Ok(inputs)
+    }
+
+    // press the direction the axis is pushed in, release the other one
+    fn axis(&mut self, which: u32, axis: Axis, value: i16) -> Vec<PadInput> {
+        let negative = value <= -THRESHOLD;
+        let positive = value >= THRESHOLD;
+
+        let name = axis.string();
+        let mut inputs = Vec::new();
+        inputs.extend(self.set(which, format!("{}-", name), negative));
+        inputs.extend(self.set(which, format!("{}+", name), positive));
+        inputs
+    }
+
+    // report a button or direction if it changed
+    fn set(&mut self, which: u32, name: String, pressed: bool) -> Option<PadInput> {
+        let held = self
+            .held
+            .iter()
+            .position(|(id, held)| *id == which && *held == name);
+
+        match (held, pressed) {
+            (None, true) => self.held.push((which, name.clone())),
+            (Some(i), false) => {
+                self.held.remove(i);
+            }
+            _ => return None,
+        }
+
+        Some(PadInput::Button(which, name, pressed))
+    }
+
+    fn release_all(&mut self, which: u32) -> Vec<PadInput> {
+        let (released, held): (Vec<_>, Vec<_>) =
+            self.held.drain(..).partition(|(id, _)| *id == which);
+        self.held = held;
+
+        released
+            .into_iter()
+            .map(|(id, name)| PadInput::Button(id, name, false))
+            .collect()
+    }
+}
@@ -1,28 +1,118 @@
-use sdl2::pixels::Color;
+use crate::filter::Filter;
+use crate::frame::Frame;
+use crate::osd::{self, Label, Osd};
+use crate::palette::Palette;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{BlendMode, Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
 use sdl2::Sdl;
+use std::time::{Duration, Instant};
 
-const GRID_WIDTH: u16 = 64;
-const GRID_HEIGHT: u16 = 32;
+// window size at scale 1, in CHIP-8 pixels
+const BASE_WIDTH: u32 = 64;
+const BASE_HEIGHT: u32 = 32;
+
+// the on-screen display is scaled up so that the window is at least
+// this many font pixels high
+const OSD_HEIGHT: u32 = 180;
+
+// the filter enlarges frames at most this many times before SDL scales
+// them the rest of the way, to keep the work on the CPU bounded
+const MAX_FILTER_FACTOR: u32 = 16;
+
+/**
+ * How the display is stretched to fill the window.
+ * Both keep the aspect ratio and fill the rest of the window with black bars.
+ *
+ * Integer: pixels are a whole number of screen pixels wide, so all of them
+ *          have the same size
+ * Fit:     pixels are as big as possible, even if not all of them have
+ *          exactly the same size
+ */
+#[derive(Clone, Copy)]
+pub enum Scaling {
+    Integer,
+    Fit,
+}
+
+impl Scaling {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(format!(
+                "Unknown scaling {}, expected integer or fit",
+                value
+            )),
+        }
+    }
+}
 
 pub struct Graphics {
     pub ctx: Sdl,               // SDL context from sdl2::init()
     pub canvas: Canvas<Window>, // canvas
-    pub pixel_length: u16,      // height and width of a single pixel square
+    pub scaling: Scaling,       // how the display is fitted into the window
+
+    // the display at its native resolution, or enlarged by the filter,
+    // scaled up by SDL when copied to the window. recreated when the
+    // resolution changes
+    texture: Option<Texture>,
+    texture_size: (u32, u32),
+
+    // latest frame, at its native resolution
+    frame: Option<Frame>,
+
+    // is `frame` newer than the texture?
+    frame_changed: bool,
+
+    // post-processing, whether it is turned on, and how much it enlarged
+    // the frame in the texture (None if it wasn't used)
+    filter: Filter,
+    filter_on: bool,
+    filter_factor: Option<u32>,
+
+    // does the window show an outdated frame?
+    dirty: bool,
+
+    last_present: Option<Instant>,
+
+    // text drawn over the display
+    pub osd: Osd,
+
+    // the on-screen display as drawn, its size in font pixels
+    // and what is on it
+    overlay: Option<Texture>,
+    overlay_size: (u32, u32),
+    overlay_labels: Vec<Label>,
 }
 
 impl Graphics {
-    pub fn new() -> Result<Self, String> {
+    /**
+     * open the window.
+     * scale: size of a CHIP-8 pixel in the initial window, e.g. 20 for 1280x640
+     * filter: post-processing, applied from the start if any effect is on
+     */
+    pub fn new(scale: f32, scaling: Scaling, filter: Filter) -> Result<Self, String> {
         let ctx = sdl2::init()?;
         let video_subsys = ctx.video()?;
 
+        let width = (BASE_WIDTH as f32 * scale).round().max(1.0) as u32;
+        let height = (BASE_HEIGHT as f32 * scale).round().max(1.0) as u32;
+
+        // with allow_highdpi the window is sized in points, but drawn in
+        // physical pixels, so it stays sharp on HiDPI screens
         let window = video_subsys
-            .window("SCATERS", 1280, 640)
+            .window("SCATERS", width, height)
+            .position_centered()
+            .resizable()
+            .allow_highdpi()
             .build()
             .map_err(|e| e.to_string())?;
 
+        // keep pixels sharp when the texture is scaled up
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+
         let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
 
         canvas.set_draw_color(Color::RGB(0, 0, 0));
@@ -31,39 +121,246 @@ impl Graphics {
         Ok(Self {
             ctx,
             canvas,
-            pixel_length: 20,
+            scaling,
+            texture: None,
+            texture_size: (0, 0),
+            frame: None,
+            frame_changed: false,
+            filter,
+            filter_on: filter.is_on(),
+            filter_factor: None,
+            dirty: true,
+            last_present: None,
+            osd: Osd::new(),
+            overlay: None,
+            overlay_size: (0, 0),
+            overlay_labels: Vec::new(),
         })
     }
 
-    pub fn draw(&mut self, gfx: &[bool]) -> Result<(), String> {
-        let side_length = self.pixel_length;
-        let canvas = &mut self.canvas;
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
 
-        canvas.clear();
+        window.set_fullscreen(fullscreen)
+    }
 
-        for h in 0..GRID_HEIGHT {
-            for w in 0..GRID_WIDTH {
-                let x_pos = side_length * w;
-                let y_pos = side_length * h;
+    /**
+     * turn the filter on or off. returns whether it is on.
+     * without a configured filter, all effects are used.
+     */
+    pub fn toggle_filter(&mut self) -> bool {
+        if !self.filter.is_on() {
+            self.filter = Filter::CRT;
+        }
+        self.filter_on = !self.filter_on;
+        self.dirty = true;
+
+        self.filter_on
+    }
 
-                let index = h * GRID_WIDTH + w;
-                let color = if gfx[index as usize] {
-                    Color::RGB(0xFF, 0xFF, 0xFF)
+    /**
+     * the part of the window the display is drawn in,
+     * for a display of `width` x `height` pixels
+     */
+    fn viewport(&self, width: u32, height: u32) -> Result<Rect, String> {
+        let (out_width, out_height) = self.canvas.output_size()?;
+
+        let (view_width, view_height) = match self.scaling {
+            Scaling::Integer => {
+                let scale = (out_width / width).min(out_height / height).max(1);
+                (width * scale, height * scale)
+            }
+            Scaling::Fit => {
+                // compare out_width / out_height with width / height
+                if out_width as u64 * height as u64 > out_height as u64 * width as u64 {
+                    (out_height * width / height, out_height)
                 } else {
-                    Color::RGB(0, 0, 0)
-                };
-                canvas.set_draw_color(color);
-                canvas.fill_rect(Rect::new(
-                    x_pos.into(),
-                    y_pos.into(),
-                    side_length.into(),
-                    side_length.into(),
-                ))?;
+                    (out_width, out_width * height / width)
+                }
+            }
+        };
+
+        Ok(Rect::new(
+            (out_width as i32 - view_width as i32) / 2,
+            (out_height as i32 - view_height as i32) / 2,
+            view_width.max(1),
+            view_height.max(1),
+        ))
+    }
+
+    /**
+     * draw the display.
+     * `shades` is the brightness of each pixel, 0 (off) ~ 255 (on).
+     * the display is `width` pixels wide, e.g. 64 or 128.
+     *
+     * this only remembers the frame. it shows up in the window with the
+     * next `present`.
+     */
+    pub fn draw(&mut self, shades: &[u8], width: usize, palette: &Palette) {
+        self.frame = Some(Frame::render(shades, width, palette));
+        self.frame_changed = true;
+        self.dirty = true;
+    }
+
+    // redraw the window even if the display did not change, e.g. after a resize
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /**
+     * show the latest frame, at most once per refresh of the monitor.
+     * should be called often; it returns right away when there is nothing to do.
+     */
+    pub fn present(&mut self) -> Result<(), String> {
+        let (out_width, out_height) = self.canvas.output_size()?;
+        let osd_scale = (out_height / OSD_HEIGHT).max(1);
+        let overlay_size = (
+            (out_width + osd_scale - 1) / osd_scale,
+            (out_height + osd_scale - 1) / osd_scale,
+        );
+        let labels = self
+            .osd
+            .layout(overlay_size.0 as i32, overlay_size.1 as i32);
+
+        if labels != self.overlay_labels || overlay_size != self.overlay_size {
+            self.dirty = true;
+        }
+
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let interval = self.refresh_interval();
+        if let Some(last) = self.last_present {
+            if last.elapsed() < interval {
+                return Ok(());
+            }
+        }
+
+        let (width, height) = match &self.frame {
+            Some(frame) => (frame.width as u32, frame.height as u32),
+            None => (BASE_WIDTH, BASE_HEIGHT),
+        };
+        let view = self.viewport(width, height)?;
+
+        // filter at about the size the display is shown at
+        let filter_factor = if self.filter_on {
+            Some((view.height() / height).clamp(1, MAX_FILTER_FACTOR))
+        } else {
+            None
+        };
+
+        if self.frame_changed || filter_factor != self.filter_factor {
+            if let Some(frame) = self.frame.take() {
+                let result = self.upload(&frame, filter_factor);
+                self.frame = Some(frame);
+                result?;
+            }
+            self.frame_changed = false;
+            self.filter_factor = filter_factor;
+        }
+
+        // letterbox
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+
+        if let Some(texture) = &self.texture {
+            self.canvas.copy(texture, None, Some(view))?;
+        }
+
+        if labels != self.overlay_labels || overlay_size != self.overlay_size {
+            self.upload_overlay(&labels, overlay_size)?;
+        }
+        if let (Some(overlay), false) = (&self.overlay, labels.is_empty()) {
+            let (width, height) = overlay_size;
+            let target = Rect::new(0, 0, width * osd_scale, height * osd_scale);
+            self.canvas.copy(overlay, None, Some(target))?;
+        }
+
+        self.canvas.present();
+        self.dirty = false;
+        self.last_present = Some(Instant::now());
+
+        Ok(())
+    }
+
+    fn upload(&mut self, frame: &Frame, filter_factor: Option<u32>) -> Result<(), String> {
+        let filtered;
+        let frame = match filter_factor {
+            Some(factor) => {
+                filtered = self.filter.apply(frame, factor as usize);
+                &filtered
+            }
+            None => frame,
+        };
+        let size = (frame.width as u32, frame.height as u32);
+
+        if self.texture.is_none() || self.texture_size != size {
+            if let Some(texture) = self.texture.take() {
+                // textures are not freed automatically with unsafe_textures
+                unsafe { texture.destroy() };
+            }
+
+            let texture = self
+                .canvas
+                .texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGB24, size.0, size.1)
+                .map_err(|e| e.to_string())?;
+            self.texture = Some(texture);
+            self.texture_size = size;
+        }
+
+        if let Some(texture) = self.texture.as_mut() {
+            texture
+                .update(None, &frame.to_rgb24(), frame.width * 3)
+                .map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn upload_overlay(&mut self, labels: &[Label], size: (u32, u32)) -> Result<(), String> {
+        if self.overlay.is_none() || self.overlay_size != size {
+            if let Some(overlay) = self.overlay.take() {
+                unsafe { overlay.destroy() };
             }
+
+            let mut overlay = self
+                .canvas
+                .texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGBA32, size.0, size.1)
+                .map_err(|e| e.to_string())?;
+            overlay.set_blend_mode(BlendMode::Blend);
+            self.overlay = Some(overlay);
+            self.overlay_size = size;
         }
 
-        canvas.present();
+        let (width, height) = (size.0 as usize, size.1 as usize);
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay
+                .update(None, &osd::render(labels, width, height), width * 4)
+                .map_err(|e| e.to_string())?;
+        }
+        self.overlay_labels = labels.to_vec();
 
         Ok(())
     }
+
+    // time between two refreshes of the monitor the window is on
+    fn refresh_interval(&self) -> Duration {
+        let window = self.canvas.window();
+        let refresh_rate = window
+            .display_index()
+            .and_then(|index| window.subsystem().current_display_mode(index))
+            .map(|mode| mode.refresh_rate)
+            .unwrap_or(0);
+
+        // unknown refresh rates are reported as 0
+        let refresh_rate = if refresh_rate > 0 { refresh_rate } else { 60 };
+        Duration::from_secs(1) / refresh_rate as u32
+    }
 }
@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+
+// a key pressed or released at an emulated cycle
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InputEvent {
+    // number of instructions run before it takes effect
+    pub cycle: u64,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+/**
+ * Key presses and releases waiting for the CPU
+ *
+ * The CPU applies each event right before running its cycle, so input
+ * lands at the same point of the program however the host was busy.
+ * Replaying the same events gives the same run, e.g. for movies.
+ */
+pub struct InputQueue {
+    // by cycle
+    events: VecDeque<InputEvent>,
+
+    // fewest cycles a key stays pressed or released, so that the program
+    // sees a press even if the release came right after it
+    pub min_hold: u64,
+
+    // state of each key after the queued events, and the cycle it changed at
+    last: [(bool, Option<u64>); 16],
+
+    // host inputs holding keys down, e.g. a keyboard key and a controller
+    // button bound to the same key, and the key
+    holders: Vec<(String, usize)>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        InputQueue {
+            events: VecDeque::new(),
+            min_hold: 0,
+            last: [(false, None); 16],
+            holders: Vec::new(),
+        }
+    }
+
+    /**
+     * press or release `key` for the host input `holder` at `cycle`.
+     * the key is only released once every input holding it was released.
+     * returns the cycle the event was queued at, if it was.
+     */
+    pub fn hold(&mut self, holder: &str, key: usize, pressed: bool, cycle: u64) -> Option<u64> {
+        let held = self
+            .holders
+            .iter()
+            .position(|(name, bound)| name == holder && *bound == key);
+
+        match (held, pressed) {
+            (None, true) => self.holders.push((holder.to_string(), key)),
+            (Some(i), false) => {
+                self.holders.remove(i);
+            }
+            _ => (),
+        }
+
+        if pressed || self.holders.iter().all(|&(_, bound)| bound != key) {
+            self.push(key, pressed, cycle)
+        } else {
+            None
+        }
+    }
+
+    /**
+     * press or release `key` at `cycle`, or later if the key changed less
+     * than `min_hold` cycles before. repeated presses are dropped.
+     * returns the cycle the event was queued at, if it was.
+     */
+    pub fn push(&mut self, key: usize, pressed: bool, cycle: u64) -> Option<u64> {
+        let (state, changed) = self.last[key];
+        if state == pressed {
+            return None;
+        }
+
+        let cycle = match changed {
+            Some(changed) => cycle.max(changed.saturating_add(self.min_hold)),
+            None => cycle,
+        };
+        self.last[key] = (pressed, Some(cycle));
+
+        // after the events at the same cycle, to keep their order
+        let at = self
+            .events
+            .iter()
+            .position(|event| event.cycle > cycle)
+            .unwrap_or(self.events.len());
+        self.events.insert(
+            at,
+            InputEvent {
+                cycle,
+                key,
+                pressed,
+            },
+        );
+
+        Some(cycle)
+    }
+
+    // the next event, if it is due at `cycle`
+    pub fn pop(&mut self, cycle: u64) -> Option<InputEvent> {
+        match self.events.front() {
+            Some(event) if event.cycle <= cycle => self.events.pop_front(),
+            _ => None,
+        }
+    }
+
+    // take every event out of the queue, with the cycle it is due at
+    #[cfg(test)]
+    pub fn drain(&mut self) -> Vec<(u64, usize, bool)> {
+        let mut events = Vec::new();
+        while let Some(event) = self.pop(u64::MAX) {
+            events.push((event.cycle, event.key, event.pressed));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quick_tap_lasts_min_hold() {
+        let mut input = InputQueue::new();
+        input.min_hold = 16;
+        // pressed and released in the same poll
+        input.push(5, true, 100);
+        input.push(5, false, 100);
+
+        assert_eq!(input.drain(), vec![(100, 5, true), (116, 5, false)]);
+    }
+
+    #[test]
+    fn events_stay_in_cycle_order() {
+        let mut input = InputQueue::new();
+        input.min_hold = 16;
+        input.push(5, true, 100);
+        input.push(5, false, 100);
+        // later than the release of 5 in the queue, but due before it
+        input.push(3, true, 102);
+        input.push(3, false, 102);
+        input.push(5, true, 101);
+
+        assert_eq!(
+            input.drain(),
+            vec![
+                (100, 5, true),
+                (102, 3, true),
+                (116, 5, false),
+                (118, 3, false),
+                (132, 5, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn same_cycle_keeps_push_order() {
+        let mut input = InputQueue::new();
+        input.push(1, true, 10);
+        input.push(2, true, 10);
+        input.push(3, true, 10);
+
+        assert_eq!(
+            input.drain(),
+            vec![(10, 1, true), (10, 2, true), (10, 3, true)]
+        );
+    }
+
+    #[test]
+    fn repeated_states_are_dropped() {
+        let mut input = InputQueue::new();
+        assert_eq!(input.push(5, false, 0), None);
+        assert_eq!(input.push(5, true, 0), Some(0));
+        assert_eq!(input.push(5, true, 5), None);
+
+        assert_eq!(input.drain(), vec![(0, 5, true)]);
+    }
+
+    #[test]
+    fn pops_only_due_events() {
+        let mut input = InputQueue::new();
+        input.push(5, true, 10);
+
+        assert_eq!(input.pop(9), None);
+        assert_eq!(
+            input.pop(10),
+            Some(InputEvent {
+                cycle: 10,
+                key: 5,
+                pressed: true
+            })
+        );
+        assert_eq!(input.pop(11), None);
+    }
+
+    #[test]
+    fn key_stays_pressed_while_held_elsewhere() {
+        let mut input = InputQueue::new();
+        input.hold("key w", 5, true, 0);
+        input.hold("pad 0 dpup", 5, true, 10);
+        input.hold("key w", 5, false, 20);
+        input.hold("pad 0 dpup", 5, false, 30);
+
+        assert_eq!(input.drain(), vec![(0, 5, true), (30, 5, false)]);
+    }
+
+    #[test]
+    fn holders_are_counted_per_key() {
+        let mut input = InputQueue::new();
+        input.hold("pad 0 dpup", 5, true, 0);
+        input.hold("pad 1 dpup", 5, true, 0);
+        input.hold("pad 1 a", 6, true, 0);
+        input.hold("pad 1 dpup", 5, false, 10);
+        input.hold("pad 1 a", 6, false, 10);
+        input.hold("pad 0 dpup", 5, false, 20);
+
+        assert_eq!(
+            input.drain(),
+            vec![(0, 5, true), (0, 6, true), (10, 6, false), (20, 5, false)]
+        );
+    }
+
+    #[test]
+    fn stray_release_is_harmless() {
+        let mut input = InputQueue::new();
+        // e.g. a key that was down before the window opened
+        input.hold("key w", 5, false, 0);
+        input.hold("key s", 8, true, 0);
+        input.hold("key s", 8, true, 5);
+        input.hold("key s", 8, false, 10);
+
+        assert_eq!(input.drain(), vec![(0, 8, true), (10, 8, false)]);
+    }
+}
@@ -0,0 +1,609 @@
+use crate::macros::{self, Action, Step};
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+/**
+ * The 1234/QWER/ASDF/ZXCV block on the left of the keyboard:
+ *  |---|---|---|---|       |---|---|---|---|
+ *  | 1 | 2 | 3 | 4 |       | 1 | 2 | 3 | C |
+ *  |---|---|---|---|       |---|---|---|---|
+ *  | Q | W | E | R |       | 4 | 5 | 6 | D |
+ *  |---|---|---|---|  -->  |---|---|---|---|
+ *  | A | S | D | F |       | 7 | 8 | 9 | E |
+ *  |---|---|---|---|       |---|---|---|---|
+ *  | Z | X | C | V |       | A | 0 | B | F |
+ *  |---|---|---|---|       |---|---|---|---|
+ */
+const QWERTY: [(&str, usize); 16] = [
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0xC),
+    ("Q", 0x4),
+    ("W", 0x5),
+    ("E", 0x6),
+    ("R", 0xD),
+    ("A", 0x7),
+    ("S", 0x8),
+    ("D", 0x9),
+    ("F", 0xE),
+    ("Z", 0xA),
+    ("X", 0x0),
+    ("C", 0xB),
+    ("V", 0xF),
+];
+
+// every key on the number pad stands for its own value, the operators
+// and Enter for A ~ F
+const NUMPAD: [(&str, usize); 16] = [
+    ("Keypad 0", 0x0),
+    ("Keypad 1", 0x1),
+    ("Keypad 2", 0x2),
+    ("Keypad 3", 0x3),
+    ("Keypad 4", 0x4),
+    ("Keypad 5", 0x5),
+    ("Keypad 6", 0x6),
+    ("Keypad 7", 0x7),
+    ("Keypad 8", 0x8),
+    ("Keypad 9", 0x9),
+    ("Keypad /", 0xA),
+    ("Keypad *", 0xB),
+    ("Keypad -", 0xC),
+    ("Keypad +", 0xD),
+    ("Keypad Enter", 0xE),
+    ("Keypad .", 0xF),
+];
+
+// like the Octo IDE: QWERTY, plus the arrow keys for 5 7 8 9 and space for 6,
+// which many games use to move and fire
+const OCTO_EXTRA: [(&str, usize); 5] = [
+    ("Up", 0x5),
+    ("Left", 0x7),
+    ("Down", 0x8),
+    ("Right", 0x9),
+    ("Space", 0x6),
+];
+
+// what the keys of the QWERTY block type on an AZERTY keyboard, where it
+// differs. the top row types digits only with shift
+const AZERTY_TYPED: [(char, &str); 8] = [
+    ('&', "1"),
+    ('é', "2"),
+    ('"', "3"),
+    ('\'', "4"),
+    ('A', "Q"),
+    ('Z', "W"),
+    ('Q', "A"),
+    ('W', "Z"),
+];
+
+// the D-pad and the left stick move like the arrow keys of the octo preset,
+// the bottom and right face buttons are 6 and 4, the usual action keys
+const BUTTONS: [(&str, usize); 10] = [
+    ("dpup", 0x5),
+    ("dpleft", 0x7),
+    ("dpdown", 0x8),
+    ("dpright", 0x9),
+    ("lefty-", 0x5),
+    ("leftx-", 0x7),
+    ("lefty+", 0x8),
+    ("leftx+", 0x9),
+    ("a", 0x6),
+    ("b", 0x4),
+];
+
+/**
+ * Which host keys and controller buttons press which CHIP-8 keys
+ *
+ * Keys are named like SDL scancodes ("Q", "Keypad 7", "Space", "Up", ...),
+ * case does not matter. Scancodes stand for the position of a key, not
+ * its label, so a keymap covers the same keys on every keyboard layout.
+ * The terminal only knows what was typed, so there keys are matched by
+ * the character they type, through the keyboard layout of the preset.
+ *
+ * Controller buttons are named like in SDL game controller mappings
+ * ("a", "b", "x", "y", "start", "back", "leftshoulder", "dpup", ...), stick
+ * and trigger directions by their axis and a sign ("leftx-" is the left
+ * stick pushed left, "lefty+" pushed down, "righttrigger+" ...).
+ *
+ * Keymap files are TOML. They can start from a preset, and bind each
+ * CHIP-8 key (0 ~ F) to one or more keys or buttons, replacing its bindings:
+ *
+ *   preset = "qwerty"
+ *
+ *   [keys]
+ *   5 = ["W", "Up"]
+ *   6 = "Space"
+ *
+ *   [buttons]
+ *   6 = ["a", "righttrigger+"]
+ *
+ * Keys can also fire a CHIP-8 key repeatedly while held (turbo), or play
+ * a sequence of presses (macros, see `Step`):
+ *
+ *   [turbo]
+ *   Space = { key = "6", rate = 15 }
+ *
+ *   [macros]
+ *   M = ["tap 4", "wait 10", "press 6", "wait 30", "release 6"]
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keymap {
+    // host key name in lower case, CHIP-8 key
+    keys: Vec<(String, usize)>,
+
+    // controller button or direction in lower case, CHIP-8 key
+    buttons: Vec<(String, usize)>,
+
+    // host key name in lower case, what it does instead of a CHIP-8 key
+    actions: Vec<(String, Action)>,
+
+    // characters typed in the terminal by keys other than the key of the
+    // same name, and the name of that key
+    layout: &'static [(char, &'static str)],
+}
+
+impl Keymap {
+    /**
+     * all presets bind the controller the same way.
+     *
+     * qwerty: the 1234/QWER/ASDF/ZXCV block
+     * azerty: the same block, labelled 1234/AZER/QSDF/WXCV on AZERTY
+     *         keyboards. scancodes go by position, so only the terminal
+     *         needs to know the layout
+     * numpad: the number pad
+     * octo:   qwerty with the arrow keys and space, like the Octo IDE
+     */
+    pub fn preset(name: &str) -> Option<Self> {
+        let layout: &[(char, &str)] = match name {
+            "azerty" => &AZERTY_TYPED,
+            _ => &[],
+        };
+        let keys: Vec<(&str, usize)> = match name {
+            "qwerty" | "azerty" => QWERTY.to_vec(),
+            "numpad" => NUMPAD.to_vec(),
+            "octo" => QWERTY.iter().chain(OCTO_EXTRA.iter()).cloned().collect(),
+            _ => return None,
+        };
+
+        let lowercase = |bindings: &[(&str, usize)]| {
+            bindings
+                .iter()
+                .map(|(name, index)| (name.to_lowercase(), *index))
+                .collect()
+        };
+
+        Some(Keymap {
+            keys: lowercase(&keys),
+            buttons: lowercase(&BUTTONS),
+            actions: Vec::new(),
+            layout,
+        })
+    }
+
+    pub fn preset_names() -> Vec<&'static str> {
+        vec!["qwerty", "azerty", "numpad", "octo"]
+    }
+
+    // a preset name, or the path of a keymap file
+    pub fn load(value: &str) -> Result<Self, String> {
+        match Keymap::preset(value) {
+            Some(keymap) => Ok(keymap),
+            None if Path::new(value).exists() => Keymap::read(Path::new(value), &Keymap::default()),
+            None => Err(format!(
+                "No keymap preset or file named {}. Presets are: {}",
+                value,
+                Keymap::preset_names().join(", ")
+            )),
+        }
+    }
+
+    // read a keymap file, on top of `base` unless the file names a preset
+    pub fn read(path: &Path, base: &Keymap) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Keymap::parse(&text, base).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str, base: &Keymap) -> Result<Self, String> {
+        let table = text.parse::<Value>().map_err(|e| e.to_string())?;
+
+        let mut keymap = match table.get("preset") {
+            None => base.clone(),
+            Some(Value::String(name)) => Keymap::preset(name).ok_or_else(|| {
+                format!(
+                    "Unknown keymap preset {}. Presets are: {}",
+                    name,
+                    Keymap::preset_names().join(", ")
+                )
+            })?,
+            Some(_) => return Err(String::from("preset must be a string")),
+        };
+
+        for (index, names) in parse_bindings(&table, "keys")? {
+            for name in names.iter() {
+                check_key_name(name)?;
+            }
+            bind(&mut keymap.keys, index, &names);
+        }
+
+        for (index, names) in parse_bindings(&table, "buttons")? {
+            for name in names.iter() {
+                check_button_name(name)?;
+            }
+            bind(&mut keymap.buttons, index, &names);
+        }
+
+        for (name, action) in parse_actions(&table)? {
+            check_key_name(&name)?;
+            let name = name.to_lowercase();
+            keymap.actions.retain(|(bound, _)| *bound != name);
+            keymap.actions.push((name, action));
+        }
+
+        Ok(keymap)
+    }
+
+    // the CHIP-8 key pressed by the host key `name`
+    pub fn index(&self, name: &str) -> Option<usize> {
+        lookup(&self.keys, name)
+    }
+
+    // the turbo or macro of the host key `name`
+    pub fn action(&self, name: &str) -> Option<&Action> {
+        let name = name.to_lowercase();
+        self.actions
+            .iter()
+            .find(|(bound, _)| *bound == name)
+            .map(|(_, action)| action)
+    }
+
+    // the name of the key typing `c` in the terminal, as far as it is known
+    pub fn typed_key_name(&self, c: char) -> String {
+        let name = char_key_name(c);
+        self.layout
+            .iter()
+            .find(|&&(typed, _)| char_key_name(typed) == name)
+            .map_or(name, |(_, key)| key.to_string())
+    }
+
+    // does the host key `name` do anything?
+    pub fn binds(&self, name: &str) -> bool {
+        self.index(name).is_some() || self.action(name).is_some()
+    }
+
+    // the CHIP-8 key pressed by the controller button or direction `name`
+    #[cfg(feature = "sdl")]
+    pub fn button_index(&self, name: &str) -> Option<usize> {
+        lookup(&self.buttons, name)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::preset("qwerty").unwrap()
+    }
+}
+
+/**
+ * read a table of CHIP-8 key (0 ~ F) = name or list of names.
+ * a missing table binds nothing.
+ */
+fn parse_bindings<'a>(
+    table: &'a Value,
+    section: &str,
+) -> Result<Vec<(usize, Vec<&'a str>)>, String> {
+    let entries = match table.get(section) {
+        None => return Ok(Vec::new()),
+        Some(Value::Table(entries)) => entries,
+        Some(_) => return Err(format!("{} must be a table", section)),
+    };
+
+    let mut bindings = Vec::new();
+    for (chip8_key, value) in entries.iter() {
+        let index = macros::parse_key(chip8_key)
+            .ok_or_else(|| format!("{} is not a CHIP-8 key (0 ~ F)", chip8_key))?;
+
+        let names = match value {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("{}.{} must be names", section, chip8_key))?,
+            _ => {
+                return Err(format!(
+                    "{}.{} must be a name or a list",
+                    section, chip8_key
+                ))
+            }
+        };
+
+        bindings.push((index, names));
+    }
+
+    Ok(bindings)
+}
+
+/**
+ * read the [turbo] table of host key = { key = CHIP-8 key, rate = Hz }
+ * and the [macros] table of host key = list of steps
+ */
+fn parse_actions(table: &Value) -> Result<Vec<(String, Action)>, String> {
+    let mut actions = Vec::new();
+
+    match table.get("turbo") {
+        None => (),
+        Some(Value::Table(entries)) => {
+            for (name, value) in entries.iter() {
+                let key = value
+                    .get("key")
+                    .and_then(|key| key.as_str())
+                    .and_then(macros::parse_key)
+                    .ok_or_else(|| format!("turbo.{}.key must be a CHIP-8 key (0 ~ F)", name))?;
+                let rate = match value.get("rate") {
+                    Some(Value::Float(rate)) => *rate as f32,
+                    Some(Value::Integer(rate)) => *rate as f32,
+                    _ => return Err(format!("turbo.{}.rate must be a number", name)),
+                };
+
+                actions.push((name.clone(), Action::turbo(key, rate)?));
+            }
+        }
+        Some(_) => return Err(String::from("turbo must be a table")),
+    }
+
+    match table.get("macros") {
+        None => (),
+        Some(Value::Table(entries)) => {
+            for (name, value) in entries.iter() {
+                let steps = value
+                    .as_array()
+                    .ok_or_else(|| format!("macros.{} must be a list of steps", name))?
+                    .iter()
+                    .map(|step| match step {
+                        Value::String(step) => Step::parse(step),
+                        _ => Err(format!("macros.{} must be a list of steps", name)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                actions.push((name.clone(), Action::Macro(steps)));
+            }
+        }
+        Some(_) => return Err(String::from("macros must be a table")),
+    }
+
+    Ok(actions)
+}
+
+// make `names` press the CHIP-8 key `index`, and only it, instead of
+// the names bound to it before
+fn bind(bindings: &mut Vec<(String, usize)>, index: usize, names: &[&str]) {
+    let names: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+
+    bindings.retain(|(name, bound)| *bound != index && !names.contains(name));
+    for name in names {
+        bindings.push((name, index));
+    }
+}
+
+fn lookup(bindings: &[(String, usize)], name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    bindings
+        .iter()
+        .find(|(bound, _)| *bound == name)
+        .map(|&(_, index)| index)
+}
+
+// the name of a key typing `c` on a QWERTY keyboard
+fn char_key_name(c: char) -> String {
+    match c {
+        ' ' => String::from("Space"),
+        _ => c.to_uppercase().collect(),
+    }
+}
+
+// catch typos in keymap files. without SDL there is no list of names
+#[cfg(feature = "sdl")]
+fn check_key_name(name: &str) -> Result<(), String> {
+    match sdl2::keyboard::Scancode::from_name(name) {
+        Some(_) => Ok(()),
+        None => Err(format!("Unknown key {}", name)),
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn check_key_name(_name: &str) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(feature = "sdl")]
+fn check_button_name(name: &str) -> Result<(), String> {
+    use sdl2::controller::{Axis, Button};
+
+    let name = name.to_lowercase();
+    let known = match name.strip_suffix(|c| c == '+' || c == '-') {
+        Some(axis) => Axis::from_string(axis).is_some(),
+        None => Button::from_string(&name).is_some(),
+    };
+
+    if known {
+        Ok(())
+    } else {
+        Err(format!("Unknown controller button {}", name))
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn check_button_name(_name: &str) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Keymap, String> {
+        Keymap::parse(text, &Keymap::default())
+    }
+
+    #[test]
+    fn presets() {
+        let qwerty = Keymap::preset("qwerty").unwrap();
+        assert_eq!(qwerty.index("1"), Some(0x1));
+        assert_eq!(qwerty.index("4"), Some(0xC));
+        assert_eq!(qwerty.index("x"), Some(0x0));
+        assert_eq!(qwerty.index("V"), Some(0xF));
+        assert_eq!(qwerty.index("Up"), None);
+
+        let azerty = Keymap::preset("azerty").unwrap();
+        assert_eq!(azerty.keys, qwerty.keys);
+
+        let numpad = Keymap::preset("numpad").unwrap();
+        assert_eq!(numpad.index("Keypad 7"), Some(0x7));
+        assert_eq!(numpad.index("keypad enter"), Some(0xE));
+        assert_eq!(numpad.index("Q"), None);
+
+        let octo = Keymap::preset("octo").unwrap();
+        assert_eq!(octo.index("Q"), Some(0x4));
+        assert_eq!(octo.index("Left"), Some(0x7));
+        assert_eq!(octo.index("Space"), Some(0x6));
+
+        assert_eq!(Keymap::preset("dvorak"), None);
+        for name in Keymap::preset_names() {
+            assert!(Keymap::preset(name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bindings_replace_the_key() {
+        let keymap = parse("[keys]\n5 = [\"Up\", \"I\"]\na = \"Space\"").unwrap();
+
+        assert_eq!(keymap.index("up"), Some(0x5));
+        assert_eq!(keymap.index("I"), Some(0x5));
+        // W pressed 5 before
+        assert_eq!(keymap.index("W"), None);
+        assert_eq!(keymap.index("Space"), Some(0xA));
+        assert_eq!(keymap.index("Z"), None);
+        // others are kept
+        assert_eq!(keymap.index("Q"), Some(0x4));
+    }
+
+    #[test]
+    fn a_host_key_presses_one_chip8_key() {
+        // Q pressed 4 before
+        let keymap = parse("[keys]\n6 = \"Q\"").unwrap();
+
+        assert_eq!(keymap.index("Q"), Some(0x6));
+        assert_eq!(keymap.index("E"), None);
+    }
+
+    #[test]
+    fn preset_in_file_replaces_base() {
+        let base = parse("[keys]\n5 = \"Up\"").unwrap();
+        let keymap = Keymap::parse("preset = \"numpad\"", &base).unwrap();
+        assert_eq!(keymap.index("Up"), None);
+        assert_eq!(keymap.index("Keypad 5"), Some(0x5));
+
+        // without a preset, the file adds to the base, e.g. for a ROM
+        let keymap = Keymap::parse("[keys]\n6 = \"Space\"", &base).unwrap();
+        assert_eq!(keymap.index("Up"), Some(0x5));
+        assert_eq!(keymap.index("Space"), Some(0x6));
+    }
+
+    #[test]
+    fn buttons() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.buttons.len(), BUTTONS.len());
+        assert_eq!(lookup(&keymap.buttons, "dpup"), Some(0x5));
+        assert_eq!(lookup(&keymap.buttons, "leftx-"), Some(0x7));
+
+        let keymap = parse("[buttons]\n6 = [\"X\", \"righttrigger+\"]").unwrap();
+        assert_eq!(lookup(&keymap.buttons, "x"), Some(0x6));
+        assert_eq!(lookup(&keymap.buttons, "righttrigger+"), Some(0x6));
+        assert_eq!(lookup(&keymap.buttons, "a"), None);
+        // buttons and keys are separate
+        assert_eq!(keymap.index("x"), Some(0x0));
+    }
+
+    #[test]
+    fn actions() {
+        let keymap = parse(
+            "[turbo]\nSpace = { key = \"6\", rate = 15 }\n\
+             [macros]\nM = [\"tap 4\", \"wait 10\"]",
+        )
+        .unwrap();
+
+        assert_eq!(
+            keymap.action("space"),
+            Some(&Action::Turbo { key: 6, rate: 15.0 })
+        );
+        assert_eq!(
+            keymap.action("M"),
+            Some(&Action::Macro(vec![Step::Tap(4), Step::Wait(10)]))
+        );
+        assert!(keymap.binds("m"));
+        assert!(keymap.binds("Q"));
+        assert!(!keymap.binds("P"));
+        assert_eq!(keymap.action("Q"), None);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        for text in [
+            "keys = 5",
+            "preset = 1",
+            "preset = \"dvorak\"",
+            "[keys]\nG = \"Q\"",
+            "[keys]\n10 = \"Q\"",
+            "[keys]\n5 = 3",
+            "[keys]\n5 = [\"Q\", 3]",
+            "[buttons]\n5 = {}",
+            "[turbo]\nSpace = { key = \"6\" }",
+            "[turbo]\nSpace = { key = \"G\", rate = 10 }",
+            "[turbo]\nSpace = { key = \"6\", rate = 60 }",
+            "[macros]\nM = \"tap 4\"",
+            "[macros]\nM = [\"jump\"]",
+            "[keys",
+        ] {
+            assert!(parse(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn loads_presets_by_name() {
+        assert_eq!(Keymap::load("octo"), Ok(Keymap::preset("octo").unwrap()));
+        assert!(Keymap::load("no such keymap").is_err());
+    }
+
+    #[test]
+    fn terminal_key_names() {
+        assert_eq!(char_key_name('q'), "Q");
+        assert_eq!(char_key_name('Q'), "Q");
+        assert_eq!(char_key_name(' '), "Space");
+        assert_eq!(char_key_name('1'), "1");
+    }
+
+    #[test]
+    fn azerty_terminal_keys() {
+        let azerty = Keymap::preset("azerty").unwrap();
+        let chip8_key = |c| azerty.index(&azerty.typed_key_name(c));
+
+        assert_eq!(chip8_key('a'), Some(0x4));
+        assert_eq!(chip8_key('Z'), Some(0x5));
+        assert_eq!(chip8_key('q'), Some(0x7));
+        assert_eq!(chip8_key('w'), Some(0xA));
+        assert_eq!(chip8_key('&'), Some(0x1));
+        assert_eq!(chip8_key('é'), Some(0x2));
+        assert_eq!(chip8_key('2'), Some(0x2));
+        assert_eq!(chip8_key('e'), Some(0x6));
+        assert_eq!(chip8_key('m'), None);
+
+        // a keymap file on top keeps the layout
+        let keymap = Keymap::parse("[keys]\n6 = \"Space\"", &azerty).unwrap();
+        assert_eq!(keymap.index(&keymap.typed_key_name('a')), Some(0x4));
+
+        let qwerty = Keymap::preset("qwerty").unwrap();
+        assert_eq!(qwerty.index(&qwerty.typed_key_name('a')), Some(0x7));
+    }
+}
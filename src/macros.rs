@@ -0,0 +1,306 @@
+use crate::input::InputQueue;
+
+// fastest autofire. a press has to last a frame for programs to see it,
+// so faster would only be slowed down
+const MAX_TURBO_RATE: f32 = 30.0;
+
+// longest wait in a macro, in frames (a minute)
+const MAX_WAIT: u64 = 3600;
+
+/**
+ * What a host key can do instead of pressing a CHIP-8 key
+ *
+ * Turbo: press and release `key` `rate` times a second while held
+ * Macro: play a sequence of steps when pressed
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum Action {
+    Turbo { key: usize, rate: f32 },
+    Macro(Vec<Step>),
+}
+
+/**
+ * A step of a macro
+ *
+ * "tap 5":     press and release 5, taking 2 frames
+ * "press 5":   press 5 and keep it down
+ * "release 5": let go of 5
+ * "wait 10":   wait 10 frames (1/60 s each), at most 3600
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Step {
+    Tap(usize),
+    Press(usize),
+    Release(usize),
+    Wait(u64),
+}
+
+impl Action {
+    pub fn turbo(key: usize, rate: f32) -> Result<Self, String> {
+        if !(rate > 0.0 && rate <= MAX_TURBO_RATE) {
+            return Err(format!(
+                "Turbo rate must be above 0 and at most {} Hz",
+                MAX_TURBO_RATE
+            ));
+        }
+
+        Ok(Action::Turbo { key, rate })
+    }
+}
+
+impl Step {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let words: Vec<&str> = value.split_whitespace().collect();
+
+        let step = match words.as_slice() {
+            ["tap", key] => parse_key(key).map(Step::Tap),
+            ["press", key] => parse_key(key).map(Step::Press),
+            ["release", key] => parse_key(key).map(Step::Release),
+            ["wait", frames] => frames
+                .parse()
+                .ok()
+                .filter(|&frames| frames <= MAX_WAIT)
+                .map(Step::Wait),
+            _ => None,
+        };
+
+        step.ok_or_else(|| {
+            format!(
+                "Unknown macro step {}, expected tap <key>, press <key>, release <key> or wait <frames> (up to {})",
+                value, MAX_WAIT
+            )
+        })
+    }
+}
+
+// a CHIP-8 key, 0 ~ F
+pub fn parse_key(value: &str) -> Option<usize> {
+    usize::from_str_radix(value, 16)
+        .ok()
+        .filter(|&key| key < 16)
+}
+
+/**
+ * queue the steps of a macro, starting at `cycle`.
+ * `holder` names the macro, so keys held by other inputs stay down.
+ * `frame` is the number of cycles in a frame.
+ * returns the cycle the macro is over at.
+ */
+pub fn play(holder: &str, steps: &[Step], input: &mut InputQueue, cycle: u64, frame: u64) -> u64 {
+    let mut at = cycle;
+    let mut end = cycle;
+
+    for step in steps.iter() {
+        let queued = match *step {
+            Step::Tap(key) => {
+                let pressed = input.hold(holder, key, true, at);
+                let released = input.hold(holder, key, false, at.saturating_add(frame));
+                at = at.saturating_add(2 * frame);
+                pressed.max(released)
+            }
+            Step::Press(key) => input.hold(holder, key, true, at),
+            Step::Release(key) => input.hold(holder, key, false, at),
+            Step::Wait(frames) => {
+                at = at.saturating_add(frames.saturating_mul(frame));
+                None
+            }
+        };
+
+        end = end.max(at).max(queued.unwrap_or(0));
+    }
+
+    end
+}
+
+/**
+ * Autofire running while its host key is held
+ *
+ * The presses and releases are queued a little ahead, up to the cycle
+ * given to `feed`, which is called before every slice of instructions.
+ */
+pub struct Turbo {
+    // host key that started it
+    pub host_key: String,
+    key: usize,
+
+    // name it holds the key under in the input queue
+    holder: String,
+
+    // cycles between a press and a release
+    half_period: u64,
+
+    // cycle of the next press or release to queue
+    next: u64,
+    pressed: bool,
+}
+
+impl Turbo {
+    // `fps` is the number of cycles in a second
+    pub fn new(host_key: &str, key: usize, rate: f32, fps: u32, cycle: u64) -> Self {
+        let half_period = (fps as f32 / rate / 2.0).round().max(1.0) as u64;
+
+        let host_key = host_key.to_lowercase();
+
+        Turbo {
+            holder: format!("turbo {}", host_key),
+            host_key,
+            key,
+            half_period,
+            next: cycle,
+            pressed: false,
+        }
+    }
+
+    // queue the presses and releases before `until`
+    pub fn feed(&mut self, input: &mut InputQueue, until: u64) {
+        while self.next < until {
+            self.pressed = !self.pressed;
+            input.hold(&self.holder, self.key, self.pressed, self.next);
+            self.next += self.half_period;
+        }
+    }
+
+    // let go of the key, after what was queued already
+    pub fn stop(self, input: &mut InputQueue, cycle: u64) {
+        input.hold(&self.holder, self.key, false, cycle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: u64 = 16;
+
+    fn steps(steps: &[&str]) -> Vec<Step> {
+        steps
+            .iter()
+            .map(|step| Step::parse(step).unwrap())
+            .collect()
+    }
+
+    fn queue() -> InputQueue {
+        let mut input = InputQueue::new();
+        input.min_hold = FRAME;
+        input
+    }
+
+    #[test]
+    fn parses_steps() {
+        assert_eq!(Step::parse("tap a"), Ok(Step::Tap(0xA)));
+        assert_eq!(Step::parse("press F"), Ok(Step::Press(0xF)));
+        assert_eq!(Step::parse("  release   0 "), Ok(Step::Release(0)));
+        assert_eq!(Step::parse("wait 3600"), Ok(Step::Wait(3600)));
+    }
+
+    #[test]
+    fn rejects_bad_steps() {
+        for step in [
+            "",
+            "tap",
+            "tap 10",
+            "tap g",
+            "hold 5",
+            "press 5 6",
+            "wait -1",
+            "wait 3601",
+            "wait 18446744073709551615",
+        ] {
+            assert!(Step::parse(step).is_err(), "{:?}", step);
+        }
+    }
+
+    #[test]
+    fn rejects_fast_turbo() {
+        assert!(Action::turbo(6, 30.0).is_ok());
+        assert!(Action::turbo(6, 31.0).is_err());
+        assert!(Action::turbo(6, 0.0).is_err());
+        assert!(Action::turbo(6, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn plays_steps_in_time() {
+        let mut input = queue();
+        let steps = steps(&["tap 4", "wait 2", "press 6", "wait 1", "release 6"]);
+        let end = play("macro m", &steps, &mut input, 10, FRAME);
+
+        assert_eq!(
+            input.drain(),
+            vec![(10, 4, true), (26, 4, false), (74, 6, true), (90, 6, false)]
+        );
+        assert_eq!(end, 90);
+    }
+
+    #[test]
+    fn end_includes_delayed_events() {
+        let mut input = queue();
+        // released right away, but held for a frame by the queue
+        let end = play(
+            "macro m",
+            &steps(&["press 6", "release 6"]),
+            &mut input,
+            0,
+            FRAME,
+        );
+
+        assert_eq!(input.drain(), vec![(0, 6, true), (16, 6, false)]);
+        assert_eq!(end, 16);
+    }
+
+    #[test]
+    fn long_waits_do_not_overflow() {
+        let mut input = queue();
+        let end = play(
+            "macro m",
+            &steps(&["wait 3600", "tap 1"]),
+            &mut input,
+            u64::MAX - 10,
+            FRAME,
+        );
+        assert_eq!(end, u64::MAX);
+    }
+
+    #[test]
+    fn turbo_toggles_until_stopped() {
+        let mut input = queue();
+        // 1000 cycles a second, 15 Hz: 33 cycles pressed, 33 released
+        let mut turbo = Turbo::new("Space", 6, 15.0, 1000, 5);
+        turbo.feed(&mut input, 21);
+        turbo.feed(&mut input, 150);
+        turbo.stop(&mut input, 150);
+
+        assert_eq!(
+            input.drain(),
+            vec![
+                (5, 6, true),
+                (38, 6, false),
+                (71, 6, true),
+                (104, 6, false),
+                (137, 6, true),
+                (153, 6, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn macro_leaves_held_keys_down() {
+        let mut input = queue();
+        input.hold("key w", 5, true, 0);
+        play("macro m", &steps(&["tap 5"]), &mut input, 10, FRAME);
+        input.hold("key w", 5, false, 100);
+
+        assert_eq!(input.drain(), vec![(0, 5, true), (100, 5, false)]);
+    }
+
+    #[test]
+    fn turbo_leaves_held_keys_down() {
+        let mut input = queue();
+        input.hold("pad 0 a", 6, true, 0);
+        let mut turbo = Turbo::new("Space", 6, 15.0, 1000, 5);
+        turbo.feed(&mut input, 150);
+        turbo.stop(&mut input, 150);
+        input.hold("pad 0 a", 6, false, 200);
+
+        assert_eq!(input.drain(), vec![(0, 6, true), (200, 6, false)]);
+    }
+}
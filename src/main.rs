@@ -1,24 +1,146 @@
+mod audio;
+mod config;
 mod cpu;
+mod diff;
 mod emu;
+#[cfg(feature = "sdl")]
+mod filter;
 mod font;
+mod frame;
+#[cfg(feature = "sdl")]
+mod gamepad;
+#[cfg(feature = "sdl")]
 mod gfx;
+mod input;
+mod keymap;
 mod lib;
+mod macros;
+mod options;
+mod pacing;
+#[cfg(feature = "sdl")]
+mod osd;
+#[cfg(feature = "sdl")]
+mod osd_font;
+mod palette;
+mod phosphor;
+mod quirks;
+mod recorder;
+mod rng;
+mod screenshot;
+#[cfg(feature = "sdl")]
+mod sdl_audio;
+mod state;
+mod storage;
+mod synth;
+mod term;
+mod video;
+mod wav;
 
 use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use config::Config;
 use emu::Emulator;
+use options::{Display, Options};
+use wav::WavSink;
 
 fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: ./scaters <path-to-rom>");
-        std::process::exit(1);
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", Options::usage());
+        return Ok(());
     }
 
-    let rom_file = &args[1];
+    if args.first().map(String::as_str) == Some("state-diff") {
+        return diff::run(&args[1..]);
+    }
+
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n", e);
+            eprintln!("{}", Options::usage());
+            std::process::exit(1);
+        }
+    };
+
+    let config = Config::load(options.config.as_deref())?;
+
+    let mut emu = Emulator::new();
+    let tone = options.tone(config.tone);
+    tone.validate()?;
+    emu.set_tone(tone);
+    emu.set_pacing(options.pacing);
+    emu.set_quirks(options.quirks.unwrap_or(config.quirks));
+    emu.set_keymap(options.keymap.or(config.keymap).unwrap_or_default());
+    emu.set_palette(options.palette.or(config.palette).unwrap_or_default());
+    if let Some(persistence) = options.persistence.or(config.persistence) {
+        emu.set_persistence(persistence);
+    }
+    emu.set_screenshot_options(
+        PathBuf::from(&options.screenshot_dir),
+        options.screenshot_scale,
+    );
+    emu.set_rng(rng::build(&options.rng, options.seed)?);
+    emu.load_rom(&options.rom_file);
+
+    if emu.has_autosave() {
+        let resume = match (options.resume, &options.display) {
+            (Some(resume), _) => resume,
+            // nobody is there to answer
+            (None, Display::Headless) => false,
+            (None, _) => ask_resume(),
+        };
+
+        // a broken autosave must not keep the ROM from starting
+        if resume {
+            if let Err(e) = emu.resume() {
+                eprintln!("Could not resume, starting from scratch: {}", e);
+                if let Err(e) = emu.discard_autosave() {
+                    eprintln!("Could not delete the autosave: {}", e);
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &options.record_gif {
+        emu.start_recording(Path::new(path))?;
+    }
+    if let Some(path) = &options.video_out {
+        emu.start_video(path)?;
+    }
+    if let Some(path) = &options.wav_out {
+        emu.add_audio(Box::new(WavSink::create(path)?));
+    }
 
-    let mut emu = Emulator::new()?;
-    emu.load_rom(rom_file);
-    emu.run_loop()?;
+    match options.display {
+        #[cfg(feature = "sdl")]
+        Display::Sdl => {
+            let filter = options.filter.or(config.filter).unwrap_or_default();
+            emu.run_loop(options.scale, options.scaling, filter)?
+        }
+        Display::Terminal(style) => emu.run_terminal_loop(style)?,
+        Display::Headless => emu.run_headless(options.frames.unwrap_or(0))?,
+    }
     Ok(())
 }
+
+// ask on the terminal whether to continue from the autosave
+fn ask_resume() -> bool {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return false;
+    }
+
+    // on stderr, as stdout may be a video stream
+    eprint!("Resume from where you left off last time? [Y/n] ");
+    io::stderr().flush().ok();
+
+    let mut answer = String::new();
+    if stdin.lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    !answer.trim().to_lowercase().starts_with('n')
+}
@@ -0,0 +1,339 @@
+#[cfg(feature = "sdl")]
+use crate::filter::Filter;
+#[cfg(feature = "sdl")]
+use crate::gfx::Scaling;
+use crate::keymap::Keymap;
+use crate::pacing::Pacing;
+use crate::palette::Palette;
+use crate::phosphor::Persistence;
+use crate::quirks::Quirks;
+use crate::rng::RngKind;
+use crate::synth::{Tone, Waveform};
+use crate::term::TermStyle;
+
+const USAGE: &str = "Usage: ./scaters [options] <path-to-rom>
+       ./scaters state-diff <a.state> <b.state>
+
+Options:
+  --help            show this message
+  --display <kind>  where to draw the display. one of
+                      sdl             a window (default)
+                      term            the terminal, with half blocks
+                      braille         the terminal, with braille characters
+                      none            nowhere. runs as fast as possible for
+                                      --frames frames, e.g. with --record-gif
+  --frames <n>      with --display none, how many frames (1/60 s) to run
+  --scale <n>       size of a CHIP-8 pixel in the initial window (default 20)
+  --scaling <mode>  how the display fills the window, keeping its aspect ratio
+                      fit             as big as possible (default)
+                      integer         only whole multiples of the display size
+  --filter <f>      make the window look like a CRT, toggled with F3. none
+                    (default), crt (all effects) or some of scanlines, grid,
+                    bloom and curvature, separated by commas
+  --pacing <mode>   how the emulator keeps to real time. one of
+                      frame           run 1/60 s of instructions at a time,
+                                      on schedule (default)
+                      audio           keep the sound card busy, for sound
+                                      without gaps
+                      sleep           sleep after every instruction
+  --keymap <k>      which keys press the CHIP-8 keys. a preset (qwerty
+                    (default), azerty, numpad, octo) or a keymap file
+  --palette <p>     colors of the display. either a preset (classic, green,
+                    amber, lcd, octo) or 2 to 4 colors, e.g. #000000,#33ff66
+  --persistence <p> let pixels glow after they are turned off, against flicker.
+                    off (default), blend (show the last two frames) or the
+                    number of frames pixels take to fade out
+  --screenshot-dir <dir>
+                    where F12 saves screenshots and F10 saves GIFs
+                    (default: current directory)
+  --screenshot-scale <n>
+                    enlarge screenshots, GIFs and videos n times
+                    (default 1, native resolution)
+  --record-gif <file>
+                    record the display into an animated GIF from the start
+  --video-out <file>
+                    write the display as a raw y4m video at 60 fps, e.g. for
+                    ffmpeg. - writes to stdout. uses --screenshot-scale
+  --wav-out <file>  write the sound into a WAV file, in emulated time
+  --waveform <w>    shape of the beep: square (default), triangle, sine or noise
+  --frequency <hz>  pitch of the beep (default 440)
+  --volume <v>      loudness of the beep, 0.0 ~ 1.0 (default 0.25)
+  --attack <ms>     how long the beep takes to fade in (default 2)
+  --release <ms>    how long the beep takes to fade out (default 2)
+  --quirks <q>      behaviors of other interpreters to copy. none, or some of
+                      key-release     FX0A waits for the key to be released,
+                                      like the COSMAC VIP (default)
+                      key-beep        FX0A beeps while the key is held
+                    separated by commas
+  --config <file>   read settings from <file> instead of the default config
+  --seed <n>        seed the random number generator (CXNN) for reproducible
+                    runs. only with --rng seeded or vip:<file>
+  --resume          continue from the autosave of the ROM without asking
+  --no-resume       start from scratch without asking
+  --rng <source>    where CXNN gets its random bytes from. one of
+                      seeded          seeded generator (default)
+                      thread          the operating system's generator
+                      vip:<file>      the COSMAC VIP interpreter's generator.
+                                      <file> is a dump of the VIP interpreter
+                      script:<file>   bytes of <file>, repeated";
+
+pub enum Display {
+    #[cfg(feature = "sdl")]
+    Sdl,
+    Terminal(TermStyle),
+    // no display, for recording
+    Headless,
+}
+
+impl Display {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            #[cfg(feature = "sdl")]
+            "sdl" => Ok(Display::Sdl),
+            #[cfg(not(feature = "sdl"))]
+            "sdl" => Err(String::from("scaters was built without SDL support")),
+            "term" => Ok(Display::Terminal(TermStyle::HalfBlock)),
+            "braille" => Ok(Display::Terminal(TermStyle::Braille)),
+            "none" => Ok(Display::Headless),
+            _ => Err(format!("Unknown display {}", value)),
+        }
+    }
+}
+
+/**
+ * Command line options
+ */
+pub struct Options {
+    pub rom_file: String,
+
+    pub display: Display,
+
+    // frames to run without a display
+    pub frames: Option<u64>,
+
+    #[cfg(feature = "sdl")]
+    pub scale: f32,
+
+    #[cfg(feature = "sdl")]
+    pub scaling: Scaling,
+
+    #[cfg(feature = "sdl")]
+    pub filter: Option<Filter>,
+
+    pub pacing: Pacing,
+
+    pub keymap: Option<Keymap>,
+
+    pub quirks: Option<Quirks>,
+
+    pub palette: Option<Palette>,
+
+    pub persistence: Option<Persistence>,
+
+    pub screenshot_dir: String,
+
+    pub screenshot_scale: usize,
+
+    // GIF to record from the start
+    pub record_gif: Option<String>,
+
+    // y4m video to write, "-" for stdout
+    pub video_out: Option<String>,
+
+    // WAV file to write the sound into
+    pub wav_out: Option<String>,
+
+    // what the beep sounds like. None if not given
+    pub waveform: Option<Waveform>,
+    pub frequency: Option<f32>,
+    pub volume: Option<f32>,
+    pub attack: Option<f32>,
+    pub release: Option<f32>,
+
+    // config file to use instead of the default one
+    pub config: Option<String>,
+
+    // seed for CXNN. random if not given
+    pub seed: Option<u64>,
+
+    // random source for CXNN
+    pub rng: RngKind,
+
+    // resume from the autosave? ask if not given
+    pub resume: Option<bool>,
+}
+
+impl Options {
+    pub fn usage() -> &'static str {
+        USAGE
+    }
+
+    /**
+     * parse options from command line arguments.
+     * `args` should not contain the program name.
+     */
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut rom_file = None;
+        #[cfg(feature = "sdl")]
+        let mut display = Display::Sdl;
+        #[cfg(not(feature = "sdl"))]
+        let mut display = Display::Terminal(TermStyle::HalfBlock);
+        #[cfg(feature = "sdl")]
+        let mut scale: f32 = 20.0;
+        #[cfg(feature = "sdl")]
+        let mut scaling = Scaling::Fit;
+        #[cfg(feature = "sdl")]
+        let mut filter = None;
+        let mut pacing = Pacing::Frame;
+        let mut keymap = None;
+        let mut quirks = None;
+        let mut palette = None;
+        let mut persistence = None;
+        let mut screenshot_dir = String::from(".");
+        let mut screenshot_scale = 1;
+        let mut record_gif = None;
+        let mut video_out = None;
+        let mut wav_out = None;
+        let mut frames = None;
+        let mut waveform = None;
+        let mut frequency = None;
+        let mut volume = None;
+        let mut attack = None;
+        let mut release = None;
+        let mut config = None;
+        let mut seed = None;
+        let mut rng = RngKind::Seeded;
+        let mut resume = None;
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--display" => display = Display::parse(next_value(&mut iter, arg)?)?,
+                "--frames" => frames = Some(parse_number(next_value(&mut iter, arg)?, arg)?),
+                #[cfg(feature = "sdl")]
+                "--scale" => {
+                    scale = parse_number(next_value(&mut iter, arg)?, arg)?;
+                    if scale.is_nan() || scale <= 0.0 {
+                        return Err(String::from("--scale must be positive"));
+                    }
+                }
+                #[cfg(feature = "sdl")]
+                "--scaling" => scaling = Scaling::parse(next_value(&mut iter, arg)?)?,
+                #[cfg(feature = "sdl")]
+                "--filter" => filter = Some(Filter::parse(next_value(&mut iter, arg)?)?),
+                "--pacing" => pacing = Pacing::parse(next_value(&mut iter, arg)?)?,
+                "--keymap" => keymap = Some(Keymap::load(next_value(&mut iter, arg)?)?),
+                "--quirks" => quirks = Some(Quirks::parse(next_value(&mut iter, arg)?)?),
+                "--palette" => palette = Some(Palette::parse(next_value(&mut iter, arg)?)?),
+                "--persistence" => {
+                    persistence = Some(Persistence::parse(next_value(&mut iter, arg)?)?)
+                }
+                "--screenshot-dir" => screenshot_dir = next_value(&mut iter, arg)?.clone(),
+                "--screenshot-scale" => {
+                    screenshot_scale = parse_number(next_value(&mut iter, arg)?, arg)?;
+                }
+                "--record-gif" => record_gif = Some(next_value(&mut iter, arg)?.clone()),
+                "--video-out" => video_out = Some(next_value(&mut iter, arg)?.clone()),
+                "--waveform" => waveform = Some(Waveform::parse(next_value(&mut iter, arg)?)?),
+                "--frequency" => frequency = Some(parse_number(next_value(&mut iter, arg)?, arg)?),
+                "--volume" => volume = Some(parse_number(next_value(&mut iter, arg)?, arg)?),
+                "--attack" => {
+                    let ms: f32 = parse_number(next_value(&mut iter, arg)?, arg)?;
+                    attack = Some(ms / 1000.0);
+                }
+                "--release" => {
+                    let ms: f32 = parse_number(next_value(&mut iter, arg)?, arg)?;
+                    release = Some(ms / 1000.0);
+                }
+                "--wav-out" => wav_out = Some(next_value(&mut iter, arg)?.clone()),
+                "--config" => config = Some(next_value(&mut iter, arg)?.clone()),
+                "--seed" => {
+                    let value = next_value(&mut iter, arg)?;
+                    seed = Some(parse_number(value, arg)?);
+                }
+                "--resume" => resume = Some(true),
+                "--no-resume" => resume = Some(false),
+                "--rng" => rng = RngKind::parse(next_value(&mut iter, arg)?)?,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ if rom_file.is_none() => rom_file = Some(arg.clone()),
+                _ => return Err(format!("Unexpected argument {}", arg)),
+            }
+        }
+
+        let rom_file = rom_file.ok_or_else(|| String::from("No ROM file given"))?;
+
+        if let (Display::Headless, None) = (&display, frames) {
+            return Err(String::from("--display none requires --frames"));
+        }
+
+        // a seed that does nothing would make the run look reproducible
+        if seed.is_some() && !rng.is_seedable() {
+            return Err(String::from(
+                "--seed only works with --rng seeded or vip:<file>, the other sources cannot be seeded",
+            ));
+        }
+
+        // both would write to the terminal
+        if let (Display::Terminal(_), Some("-")) = (&display, video_out.as_deref()) {
+            return Err(String::from(
+                "--video-out - cannot be used with a terminal display, which draws on stdout as well",
+            ));
+        }
+
+        Ok(Options {
+            rom_file,
+            display,
+            frames,
+            #[cfg(feature = "sdl")]
+            scale,
+            #[cfg(feature = "sdl")]
+            scaling,
+            #[cfg(feature = "sdl")]
+            filter,
+            pacing,
+            keymap,
+            quirks,
+            palette,
+            persistence,
+            screenshot_dir,
+            screenshot_scale,
+            record_gif,
+            video_out,
+            wav_out,
+            waveform,
+            frequency,
+            volume,
+            attack,
+            release,
+            config,
+            seed,
+            rng,
+            resume,
+        })
+    }
+
+    // `tone` with the settings given on the command line
+    pub fn tone(&self, tone: Tone) -> Tone {
+        Tone {
+            waveform: self.waveform.unwrap_or(tone.waveform),
+            frequency: self.frequency.unwrap_or(tone.frequency),
+            volume: self.volume.unwrap_or(tone.volume),
+            attack: self.attack.unwrap_or(tone.attack),
+            release: self.release.unwrap_or(tone.release),
+        }
+    }
+}
+
+fn next_value<'a>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    option: &str,
+) -> Result<&'a String, String> {
+    iter.next()
+        .ok_or_else(|| format!("Option {} requires a value", option))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, option: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for option {}", value, option))
+}
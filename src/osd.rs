@@ -0,0 +1,195 @@
+use crate::osd_font::{GLYPHS, GLYPH_HEIGHT, GLYPH_WIDTH};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// how long a message stays on screen
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+
+// messages shown at once. older ones are dropped
+const MAX_MESSAGES: usize = 3;
+
+// space around the screen edges and between lines, in font pixels
+const MARGIN: i32 = 3;
+const LINE_HEIGHT: i32 = GLYPH_HEIGHT as i32 + 3;
+
+// a character takes the width of a glyph plus a pixel of spacing
+const ADVANCE: i32 = GLYPH_WIDTH as i32 + 1;
+
+// dark box behind text, so it can be read on any palette
+const BOX_COLOR: [u8; 4] = [0x00, 0x00, 0x00, 0xB0];
+const TEXT_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+// a line of text at (x, y), in font pixels from the top left
+#[derive(Clone, PartialEq)]
+pub struct Label {
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+}
+
+/**
+ * On-screen display drawn over the window
+ *
+ * Shows messages for a few seconds (bottom left), an optional counter of
+ * frames and instructions per second (top left), and whether the emulator
+ * is paused (top right).
+ */
+pub struct Osd {
+    // newest last, with the time they were shown
+    messages: VecDeque<(String, Instant)>,
+
+    pub show_stats: bool,
+    pub paused: bool,
+
+    // the counters of the last full second
+    stats: String,
+
+    // counting since `since`
+    since: Instant,
+    frames: u32,
+    instructions: u64,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd {
+            messages: VecDeque::new(),
+            show_stats: false,
+            paused: false,
+            stats: String::from("-- FPS  -- IPS"),
+            since: Instant::now(),
+            frames: 0,
+            instructions: 0,
+        }
+    }
+
+    pub fn message(&mut self, message: String) {
+        self.messages.push_back((message, Instant::now()));
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    // the emulator ran `instructions` instructions and completed
+    // `frames` frames (1/60 s each)
+    pub fn count(&mut self, instructions: u64, frames: u32) {
+        self.instructions += instructions;
+        self.frames += frames;
+    }
+
+    /**
+     * what to show on an overlay of `width` x `height` font pixels.
+     * changes over time, as messages expire and counters update.
+     */
+    pub fn layout(&mut self, width: i32, height: i32) -> Vec<Label> {
+        self.update();
+
+        let mut labels = Vec::new();
+
+        if self.show_stats {
+            labels.push(Label {
+                x: MARGIN,
+                y: MARGIN,
+                text: self.stats.clone(),
+            });
+        }
+
+        if self.paused {
+            let text = String::from("PAUSED");
+            labels.push(Label {
+                x: width - MARGIN - text_width(&text),
+                y: MARGIN,
+                text,
+            });
+        }
+
+        let mut y = height - MARGIN - GLYPH_HEIGHT as i32;
+        for (message, _) in self.messages.iter().rev() {
+            labels.push(Label {
+                x: MARGIN,
+                y,
+                text: message.clone(),
+            });
+            y -= LINE_HEIGHT;
+        }
+
+        labels
+    }
+
+    // drop old messages, and roll the counters over every second
+    fn update(&mut self) {
+        let now = Instant::now();
+
+        while let Some((_, shown)) = self.messages.front() {
+            if now.duration_since(*shown) < MESSAGE_TIME {
+                break;
+            }
+            self.messages.pop_front();
+        }
+
+        let elapsed = now.duration_since(self.since);
+        if elapsed >= Duration::from_secs(1) {
+            let seconds = elapsed.as_secs_f64();
+            self.stats = format!(
+                "{:.0} FPS  {:.0} IPS",
+                self.frames as f64 / seconds,
+                self.instructions as f64 / seconds
+            );
+            self.since = now;
+            self.frames = 0;
+            self.instructions = 0;
+        }
+    }
+}
+
+fn text_width(text: &str) -> i32 {
+    text.chars().count() as i32 * ADVANCE - 1
+}
+
+/**
+ * draw labels onto a transparent image of `width` x `height` pixels.
+ * 4 bytes (red, green, blue, alpha) per pixel.
+ */
+pub fn render(labels: &[Label], width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = vec![0; width * height * 4];
+
+    let mut put = |x: i32, y: i32, color: [u8; 4]| {
+        if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+            let offset = (y as usize * width + x as usize) * 4;
+            pixels[offset..offset + 4].copy_from_slice(&color);
+        }
+    };
+
+    for label in labels {
+        // box with a pixel of padding
+        for y in label.y - 1..label.y + GLYPH_HEIGHT as i32 + 1 {
+            for x in label.x - 1..label.x + text_width(&label.text) + 1 {
+                put(x, y, BOX_COLOR);
+            }
+        }
+
+        for (i, c) in label.text.chars().enumerate() {
+            let glyph = glyph(c);
+            let left = label.x + i as i32 * ADVANCE;
+
+            for (dy, row) in glyph.iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if row & (0x10 >> dx) != 0 {
+                        put(left + dx as i32, label.y + dy as i32, TEXT_COLOR);
+                    }
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+// characters without a glyph are drawn as ?
+fn glyph(c: char) -> &'static [u8; GLYPH_HEIGHT] {
+    let index = match c {
+        ' '..='~' => c as usize - ' ' as usize,
+        _ => '?' as usize - ' ' as usize,
+    };
+    &GLYPHS[index]
+}
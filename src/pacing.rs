@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+// when the emulator falls further behind than this, e.g. after the window
+// was dragged, it gives up catching up instead of running at full speed
+// for a long time
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+// sound the audio pacing keeps queued ahead of the sound card
+const AUDIO_AHEAD: Duration = Duration::from_millis(50);
+
+/**
+ * How the emulator keeps to real time
+ *
+ * Sleep: sleep 1 / fps s after every instruction. simple, but sleeping
+ *        takes longer than asked for, so the emulator drifts and stutters
+ * Frame: run a frame (1/60 s worth of instructions) at a time, scheduled
+ *        by the clock. frames missed after a hiccup are caught up
+ * Audio: run a frame whenever the sound card is about to run out of sound,
+ *        so it never does. falls back to Frame without sound
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pacing {
+    Sleep,
+    Frame,
+    Audio,
+}
+
+impl Pacing {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "sleep" => Ok(Pacing::Sleep),
+            "frame" => Ok(Pacing::Frame),
+            "audio" => Ok(Pacing::Audio),
+            _ => Err(format!(
+                "Unknown pacing {}, expected sleep, frame or audio",
+                value
+            )),
+        }
+    }
+}
+
+/**
+ * Decides when the emulator runs. The main loop asks `due` whether to run
+ * now, runs if so, and then calls `wait`.
+ */
+pub struct Pacer {
+    pub pacing: Pacing,
+
+    // instructions per second, for sleep pacing
+    fps: u32,
+
+    // frames are due at start + n / 60 s
+    start: Instant,
+    frames: u64,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing, fps: u32) -> Self {
+        Pacer {
+            pacing,
+            fps,
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    // does the emulator run a whole frame at a time, or single instructions?
+    pub fn runs_frames(&self) -> bool {
+        self.pacing != Pacing::Sleep
+    }
+
+    /**
+     * should the emulator run now?
+     * buffered: sound queued for the sound card, None without one
+     */
+    pub fn due(&mut self, buffered: Option<Duration>) -> bool {
+        match (self.pacing, buffered) {
+            (Pacing::Sleep, _) => true,
+            (Pacing::Audio, Some(buffered)) => buffered < AUDIO_AHEAD,
+            (Pacing::Frame, _) | (Pacing::Audio, None) => self.frame_due(),
+        }
+    }
+
+    fn frame_due(&mut self) -> bool {
+        let now = Instant::now();
+        let deadline = self.deadline();
+        if now < deadline {
+            return false;
+        }
+
+        if now - deadline > MAX_LAG {
+            // continue from now on, as if the missed frames never were
+            self.start = now;
+            self.frames = 0;
+        }
+
+        self.frames += 1;
+        true
+    }
+
+    // when the next frame is due
+    fn deadline(&self) -> Instant {
+        self.start + Duration::from_nanos(self.frames * 1_000_000_000 / 60)
+    }
+
+    // pause the thread until the emulator might have to run again
+    pub fn wait(&self, buffered: Option<Duration>) {
+        let duration = match (self.pacing, buffered) {
+            (Pacing::Sleep, _) => Duration::from_secs(1) / self.fps.max(1),
+            // the sound card plays a millisecond of sound in the meantime
+            (Pacing::Audio, Some(_)) => Duration::from_millis(1),
+            (Pacing::Frame, _) | (Pacing::Audio, None) => {
+                // at most a millisecond, to keep handling input and
+                // redrawing the window
+                let remaining = self.deadline().saturating_duration_since(Instant::now());
+                remaining.min(Duration::from_millis(1))
+            }
+        };
+
+        if duration > Duration::ZERO {
+            std::thread::sleep(duration);
+        }
+    }
+}
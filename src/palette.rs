@@ -0,0 +1,180 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/**
+ * Colors used to draw the display
+ *
+ * colors[0]: background
+ * colors[1]: foreground. for XO-CHIP, pixels set in the first plane
+ * colors[2]: XO-CHIP pixels set in the second plane
+ * colors[3]: XO-CHIP pixels set in both planes
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Rgb; 4],
+}
+
+// named palettes, in the order the palette hotkey cycles through them
+const PRESETS: [(&str, [Rgb; 4]); 5] = [
+    (
+        "classic",
+        [
+            Rgb(0x00, 0x00, 0x00),
+            Rgb(0xFF, 0xFF, 0xFF),
+            Rgb(0xAA, 0xAA, 0xAA),
+            Rgb(0x55, 0x55, 0x55),
+        ],
+    ),
+    // P1 phosphor green monitor
+    (
+        "green",
+        [
+            Rgb(0x0A, 0x14, 0x0A),
+            Rgb(0x33, 0xFF, 0x66),
+            Rgb(0x1A, 0x99, 0x3D),
+            Rgb(0x99, 0xFF, 0xB2),
+        ],
+    ),
+    // P3 phosphor amber monitor
+    (
+        "amber",
+        [
+            Rgb(0x1A, 0x0F, 0x00),
+            Rgb(0xFF, 0xB0, 0x00),
+            Rgb(0x99, 0x66, 0x00),
+            Rgb(0xFF, 0xD8, 0x80),
+        ],
+    ),
+    // green tinted monochrome LCD
+    (
+        "lcd",
+        [
+            Rgb(0x9B, 0xBC, 0x0F),
+            Rgb(0x0F, 0x38, 0x0F),
+            Rgb(0x8B, 0xAC, 0x0F),
+            Rgb(0x30, 0x62, 0x30),
+        ],
+    ),
+    // the default colors of the Octo IDE
+    (
+        "octo",
+        [
+            Rgb(0x99, 0x66, 0x00),
+            Rgb(0xFF, 0xCC, 0x00),
+            Rgb(0xFF, 0x66, 0x00),
+            Rgb(0x66, 0x22, 0x00),
+        ],
+    ),
+];
+
+impl Palette {
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|&(name, colors)| Palette {
+                name: name.to_string(),
+                colors,
+            })
+    }
+
+    pub fn preset_names() -> Vec<&'static str> {
+        PRESETS.iter().map(|&(name, _)| name).collect()
+    }
+
+    /**
+     * the preset after this one, wrapping around.
+     * custom palettes are followed by the first preset.
+     */
+    pub fn next_preset(&self) -> Self {
+        let pos = PRESETS.iter().position(|(name, _)| *name == self.name);
+        let (name, colors) = match pos {
+            Some(pos) => PRESETS[(pos + 1) % PRESETS.len()],
+            None => PRESETS[0],
+        };
+
+        Palette {
+            name: name.to_string(),
+            colors,
+        }
+    }
+
+    /**
+     * accepted values are the name of a preset, or 2 to 4 comma separated
+     * colors in the order of `colors`, e.g. "#000000,#33ff66".
+     * missing XO-CHIP colors are derived from the first two.
+     */
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if let Some(palette) = Palette::preset(value) {
+            return Ok(palette);
+        }
+
+        if !value.contains(',') {
+            return Err(format!(
+                "Unknown palette {}. Presets are: {}",
+                value,
+                Palette::preset_names().join(", ")
+            ));
+        }
+
+        let colors = value
+            .split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Palette::from_colors(&colors)
+    }
+
+    pub fn from_colors(colors: &[Rgb]) -> Result<Self, String> {
+        let (background, foreground) = match colors {
+            [background, foreground, ..] if colors.len() <= 4 => (*background, *foreground),
+            _ => return Err(String::from("A palette needs 2 to 4 colors")),
+        };
+
+        let mut palette = Palette {
+            name: String::from("custom"),
+            colors: [
+                background,
+                foreground,
+                mix(background, foreground, 2, 3),
+                mix(background, foreground, 1, 3),
+            ],
+        };
+        palette.colors[..colors.len()].copy_from_slice(colors);
+
+        Ok(palette)
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.colors[1]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::preset("classic").unwrap()
+    }
+}
+
+// parse "#rrggbb" (the # is optional)
+pub fn parse_color(value: &str) -> Result<Rgb, String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let invalid = || format!("Invalid color {}, expected #rrggbb", value);
+
+    if hex.len() != 6 {
+        return Err(invalid());
+    }
+
+    let number = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+    Ok(Rgb((number >> 16) as u8, (number >> 8) as u8, number as u8))
+}
+
+// `a` blended towards `b` by num / den
+pub fn mix(a: Rgb, b: Rgb, num: u32, den: u32) -> Rgb {
+    let channel = |a: u8, b: u8| ((a as u32 * (den - num) + b as u32 * num) / den) as u8;
+    Rgb(channel(a.0, b.0), channel(a.1, b.1), channel(a.2, b.2))
+}
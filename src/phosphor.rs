@@ -0,0 +1,100 @@
+/**
+ * How long pixels keep glowing after they are turned off
+ *
+ * Sprites are drawn with XOR, so games erase and redraw them all the time
+ * and the display flickers. Letting pixels fade out like the phosphor of a
+ * CRT hides the flicker.
+ *
+ * Off:      pixels go dark immediately
+ * Decay(n): pixels fade out over n frames
+ * Blend:    show the last two frames on top of each other
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Persistence {
+    Off,
+    Decay(u8),
+    Blend,
+}
+
+impl Persistence {
+    // accepted values are off, blend or a number of frames
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "off" | "0" => Ok(Persistence::Off),
+            "blend" => Ok(Persistence::Blend),
+            _ => value.parse().map(Persistence::Decay).map_err(|_| {
+                format!(
+                    "Invalid persistence {}, expected off, blend or frames",
+                    value
+                )
+            }),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Persistence::Off => String::from("off"),
+            Persistence::Decay(frames) => format!("{} frames", frames),
+            Persistence::Blend => String::from("blend"),
+        }
+    }
+}
+
+/**
+ * Keeps track of how bright each pixel is.
+ * Brightness is 0 (off) ~ 255 (on)
+ */
+pub struct Phosphor {
+    pub persistence: Persistence,
+
+    // brightness left over from previous frames
+    glow: Vec<u8>,
+}
+
+impl Phosphor {
+    pub fn new(persistence: Persistence, pixels: usize) -> Self {
+        Phosphor {
+            persistence,
+            glow: vec![0; pixels],
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.persistence != Persistence::Off
+    }
+
+    /**
+     * advance by one frame (1/60 s).
+     * `gfx` is the display at the end of the frame.
+     */
+    pub fn frame(&mut self, gfx: &[bool]) {
+        for (glow, &pixel) in self.glow.iter_mut().zip(gfx.iter()) {
+            *glow = match self.persistence {
+                Persistence::Off => 0,
+                Persistence::Decay(frames) => {
+                    if pixel {
+                        0xFF
+                    } else {
+                        let step = 0xFF / frames.max(1);
+                        glow.saturating_sub(step)
+                    }
+                }
+                Persistence::Blend => {
+                    if pixel {
+                        0xFF
+                    } else {
+                        0
+                    }
+                }
+            };
+        }
+    }
+
+    // brightness of each pixel of `gfx`, including the glow of past frames
+    pub fn shade(&self, gfx: &[bool]) -> Vec<u8> {
+        gfx.iter()
+            .zip(self.glow.iter())
+            .map(|(&pixel, &glow)| if pixel { 0xFF } else { glow })
+            .collect()
+    }
+}
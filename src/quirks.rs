@@ -0,0 +1,63 @@
+/**
+ * Behaviors that differ between CHIP-8 interpreters. ROMs written for one
+ * interpreter can misbehave on another, so they can be switched.
+ *
+ * key-release: FX0A waits for a key to be pressed and then released,
+ *              like the COSMAC VIP, instead of returning on the press
+ * key-beep:    FX0A beeps while the key is held down, like the VIP
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks {
+    pub key_release: bool,
+    pub key_beep: bool,
+}
+
+impl Quirks {
+    const NAMES: [&'static str; 2] = ["key-release", "key-beep"];
+
+    /**
+     * "none", or the quirks to turn on separated by commas,
+     * e.g. "key-release,key-beep". the others are turned off.
+     */
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut quirks = Quirks {
+            key_release: false,
+            key_beep: false,
+        };
+
+        if value == "none" {
+            return Ok(quirks);
+        }
+
+        for name in value.split(',') {
+            quirks.set(name.trim(), true)?;
+        }
+
+        Ok(quirks)
+    }
+
+    pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+        match name {
+            "key-release" => self.key_release = on,
+            "key-beep" => self.key_beep = on,
+            _ => {
+                return Err(format!(
+                    "Unknown quirk {}, expected {}",
+                    name,
+                    Quirks::NAMES.join(", ")
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            key_release: true,
+            key_beep: false,
+        }
+    }
+}
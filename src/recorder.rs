@@ -0,0 +1,243 @@
+use crate::frame;
+use crate::palette::{self, Palette};
+use gif::{Encoder, Repeat};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/**
+ * Records the display into an animated GIF at 50 fps.
+ *
+ * GIFs count time in 1/100 s, and viewers show frames of 1/100 s or less
+ * for 1/10 s, so 60 fps cannot be recorded. Every GIF frame lasts a
+ * multiple of 1/50 s instead: a 1/60 s frame that would end less than
+ * 1/50 s after the previous GIF frame is left out, 1 in every 6.
+ *
+ * Pixels are stored by brightness (0 ~ 255), and the GIF palette is the
+ * emulator palette blended from background to foreground, so the colors
+ * match exactly even with phosphor persistence. A change of palette
+ * during the recording gets its own local GIF palette.
+ *
+ * Frames identical to the previous one are not written again; the
+ * previous frame is shown for longer instead.
+ */
+pub struct GifRecorder {
+    encoder: Encoder<BufWriter<File>>,
+    pub path: PathBuf,
+
+    width: usize,
+    height: usize,
+    scale: usize,
+
+    // palette the GIF was started with
+    global_palette: Palette,
+
+    // latest frame, written once it is known how long it is shown
+    pending: Option<(Vec<u8>, Palette)>,
+    pending_frames: u64,
+
+    // time covered by the frames written or left out so far, in 1/60 s,
+    // and by the frames written, in the 1/100 s GIFs count in
+    frames_written: u64,
+    centis_written: u64,
+}
+
+impl GifRecorder {
+    /**
+     * start recording a display of `width` x `height` pixels.
+     * every pixel becomes `scale` x `scale` pixels in the GIF.
+     */
+    pub fn new(
+        path: &Path,
+        width: usize,
+        height: usize,
+        scale: usize,
+        palette: &Palette,
+    ) -> Result<Self, String> {
+        let scale = scale.max(1);
+        let size = |pixels: usize| {
+            pixels
+                .checked_mul(scale)
+                .filter(|&size| size <= u16::MAX as usize)
+                .ok_or_else(|| {
+                    format!(
+                        "{} x {} pixels enlarged {} times is too big for a GIF",
+                        width, height, scale
+                    )
+                })
+        };
+        let gif_width = size(width)? as u16;
+        let gif_height = size(height)? as u16;
+
+        let to_string = |e: gif::EncodingError| format!("{}: {}", path.display(), e);
+
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut encoder = Encoder::new(
+            BufWriter::new(file),
+            gif_width,
+            gif_height,
+            &gif_palette(palette),
+        )
+        .map_err(to_string)?;
+        encoder.set_repeat(Repeat::Infinite).map_err(to_string)?;
+
+        Ok(GifRecorder {
+            encoder,
+            path: path.to_path_buf(),
+            width,
+            height,
+            scale,
+            global_palette: palette.clone(),
+            pending: None,
+            pending_frames: 0,
+            frames_written: 0,
+            centis_written: 0,
+        })
+    }
+
+    /**
+     * add the display at the end of a 1/60 s frame.
+     * `shades` is the brightness of each pixel, 0 (off) ~ 255 (on).
+     */
+    pub fn frame(&mut self, shades: &[u8], palette: &Palette) -> Result<(), String> {
+        if shades.len() != self.width * self.height {
+            return Err(String::from("The resolution changed while recording"));
+        }
+
+        if let Some((pending, pending_palette)) = &self.pending {
+            if pending.as_slice() == shades && pending_palette == palette {
+                self.pending_frames += 1;
+                return Ok(());
+            }
+        }
+
+        self.write_pending()?;
+        self.pending = Some((shades.to_vec(), palette.clone()));
+        self.pending_frames = 1;
+
+        Ok(())
+    }
+
+    // write the last frame and close the file
+    pub fn finish(mut self) -> Result<(), String> {
+        self.write_pending()?;
+
+        let path = self.path.clone();
+        let to_string = |e: std::io::Error| format!("{}: {}", path.display(), e);
+
+        let mut writer = self.encoder.into_inner().map_err(to_string)?;
+        writer.flush().map_err(to_string)
+    }
+
+    fn write_pending(&mut self) -> Result<(), String> {
+        let (shades, palette) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        // round the end of the frame to 1/50 s, rather than its length,
+        // so the rounding errors don't add up
+        self.frames_written += self.pending_frames;
+        let end_centis = (self.frames_written * 50 + 30) / 60 * 2;
+        let delay = (end_centis - self.centis_written).min(u16::MAX as u64 - 1);
+
+        // shown for less than 1/50 s, the next frame takes its place
+        if delay == 0 {
+            return Ok(());
+        }
+        self.centis_written += delay;
+
+        let mut gif_frame = gif::Frame {
+            width: (self.width * self.scale) as u16,
+            height: (self.height * self.scale) as u16,
+            delay: delay as u16,
+            buffer: frame::upscale(&shades, self.width, self.scale).into(),
+            ..gif::Frame::default()
+        };
+        if palette != self.global_palette {
+            gif_frame.palette = Some(gif_palette(&palette));
+        }
+
+        self.encoder
+            .write_frame(&gif_frame)
+            .map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+}
+
+// entry n is the color of a pixel with brightness n
+fn gif_palette(palette: &Palette) -> Vec<u8> {
+    let background = palette.background();
+    let foreground = palette.foreground();
+
+    let mut colors = Vec::with_capacity(256 * 3);
+    for shade in 0..256 {
+        let color = palette::mix(background, foreground, shade, 0xFF);
+        colors.extend_from_slice(&[color.0, color.1, color.2]);
+    }
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // record `frames` 1/60 s frames of a 2 x 1 display, returning the delays
+    // of the GIF frames
+    fn delays(name: &str, frames: &[u8]) -> Vec<u16> {
+        let path =
+            std::env::temp_dir().join(format!("scaters-{}-{}.gif", name, std::process::id()));
+        let palette = Palette::default();
+
+        let mut recorder = GifRecorder::new(&path, 2, 1, 1, &palette).unwrap();
+        for &shade in frames {
+            recorder.frame(&[shade, 0], &palette).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(File::open(&path).unwrap())
+            .unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+
+        std::fs::remove_file(&path).ok();
+        delays
+    }
+
+    #[test]
+    fn records_at_50_fps() {
+        let frames: Vec<u8> = (0..60).collect();
+        let delays = delays("50fps", &frames);
+
+        assert_eq!(delays.len(), 50);
+        assert!(delays.iter().all(|&delay| delay == 2));
+    }
+
+    #[test]
+    fn merges_repeated_frames() {
+        let mut frames = vec![0xFF; 60];
+        frames.extend(vec![0; 30]);
+        frames.push(0xFF);
+
+        assert_eq!(delays("repeated", &frames), vec![100, 50, 2]);
+    }
+
+    #[test]
+    fn delays_are_never_below_2() {
+        // changes every other frame
+        let frames: Vec<u8> = (0..120).map(|i| (i / 2) as u8).collect();
+        let delays = delays("alternating", &frames);
+
+        assert!(delays.iter().all(|&delay| delay >= 2 && delay % 2 == 0));
+        assert_eq!(delays.iter().map(|&delay| delay as u32).sum::<u32>(), 200);
+    }
+
+    #[test]
+    fn rejects_oversized_gifs() {
+        let path = std::env::temp_dir().join("scaters-oversized.gif");
+        assert!(GifRecorder::new(&path, 128, 64, 600, &Palette::default()).is_err());
+        std::fs::remove_file(&path).ok();
+    }
+}
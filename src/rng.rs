@@ -0,0 +1,336 @@
+use crate::lib::wrap_add;
+use rand::Rng;
+use std::convert::TryFrom;
+
+/**
+ * A source of random bytes for the CXNN instruction.
+ *
+ * The Cpu only ever asks for one byte at a time, so implementations can be
+ * anything from a real random number generator to a fixed list of bytes.
+ */
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    // internal state of the source, to be kept in save states
+    fn save(&self) -> Vec<u8>;
+
+    // continue from a state returned by `save`. other data is ignored
+    fn restore(&mut self, state: &[u8]);
+}
+
+/**
+ * Which random source to use, as given on the command line
+ */
+pub enum RngKind {
+    Thread,
+    Seeded,
+    Vip(String),
+    Script(String),
+}
+
+impl RngKind {
+    /**
+     * accepted values are:
+     *   thread, seeded, vip:<interpreter-dump>, script:<file>
+     */
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (name, path) = match value.find(':') {
+            Some(pos) => (&value[..pos], Some(value[pos + 1..].to_string())),
+            None => (value, None),
+        };
+
+        match (name, path) {
+            ("thread", None) => Ok(RngKind::Thread),
+            ("seeded", None) => Ok(RngKind::Seeded),
+            ("vip", Some(path)) => Ok(RngKind::Vip(path)),
+            ("script", Some(path)) => Ok(RngKind::Script(path)),
+            ("vip", None) | ("script", None) => Err(format!(
+                "Random source {} requires a file, e.g. {}:<file>",
+                name, name
+            )),
+            _ => Err(format!("Unknown random source {}", value)),
+        }
+    }
+
+    // does `--seed` change the numbers of this source?
+    pub fn is_seedable(&self) -> bool {
+        match self {
+            RngKind::Seeded | RngKind::Vip(_) => true,
+            RngKind::Thread | RngKind::Script(_) => false,
+        }
+    }
+}
+
+/**
+ * build the random source described by `kind`.
+ * `seed` is used by the generators that can be seeded, and a random seed is
+ * picked if it is not given.
+ */
+pub fn build(kind: &RngKind, seed: Option<u64>) -> Result<Box<dyn RandomSource>, String> {
+    let seed = seed.unwrap_or_else(rand::random);
+
+    let rng: Box<dyn RandomSource> = match kind {
+        RngKind::Thread => Box::new(ThreadRandom),
+        RngKind::Seeded => Box::new(SeededRng::new(seed)),
+        RngKind::Vip(path) => {
+            let dump = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            Box::new(VipRng::new(&dump, seed)?)
+        }
+        RngKind::Script(path) => {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            Box::new(ScriptedRng::new(bytes)?)
+        }
+    };
+
+    Ok(rng)
+}
+
+/**
+ * The operating system backed generator from the `rand` crate.
+ * Never reproducible.
+ */
+pub struct ThreadRandom;
+
+impl RandomSource for ThreadRandom {
+    fn next_byte(&mut self) -> u8 {
+        rand::thread_rng().gen()
+    }
+
+    // there is nothing to save, the numbers are random anyway
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore(&mut self, _state: &[u8]) {}
+}
+
+/**
+ * A seedable pseudo-random number generator.
+ *
+ * This is a xorshift64* generator. It is tiny, fast and, most importantly,
+ * fully determined by its seed, so two runs with the same seed produce the
+ * same sequence of random bytes.
+ */
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SeededRng { state: 0 };
+        rng.reseed(seed);
+        rng
+    }
+
+    // seed the generator with a random value
+    pub fn from_entropy() -> Self {
+        SeededRng::new(rand::random())
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        // xorshift gets stuck on a zero state, so scramble the seed with
+        // splitmix64 first. This also spreads out small seeds like 0, 1, 2
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        self.state = if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+impl RandomSource for SeededRng {
+    fn next_byte(&mut self) -> u8 {
+        // the high bits of xorshift64* are the strongest
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        if let Ok(bytes) = <[u8; 8]>::try_from(state) {
+            let state = u64::from_le_bytes(bytes);
+            if state != 0 {
+                self.state = state;
+            }
+        }
+    }
+}
+
+/**
+ * The generator of the original COSMAC VIP interpreter.
+ *
+ * The VIP had no hardware random number generator. Instead, the interpreter
+ * keeps a 16-bit value in register R9. For each CXNN it increments the low
+ * byte, uses it as a pointer into its own code page ($0100-$01FF), and adds
+ * the byte found there to the high byte. The high byte is the random number.
+ *
+ * Since the numbers come from the interpreter's machine code, a dump of the
+ * VIP interpreter has to be supplied. Either the full 512 byte interpreter
+ * or just its second page is accepted.
+ */
+pub struct VipRng {
+    page: [u8; 256],
+    low: u8,
+    high: u8,
+}
+
+impl VipRng {
+    pub fn new(dump: &[u8], seed: u64) -> Result<Self, String> {
+        let page = match dump.len() {
+            512 => &dump[256..],
+            256 => dump,
+            n => {
+                return Err(format!(
+                    "VIP interpreter dump must be 256 or 512 bytes long, got {}",
+                    n
+                ))
+            }
+        };
+
+        let mut rng = VipRng {
+            page: [0; 256],
+            low: seed as u8,
+            high: (seed >> 8) as u8,
+        };
+        rng.page.copy_from_slice(page);
+        Ok(rng)
+    }
+}
+
+impl RandomSource for VipRng {
+    fn next_byte(&mut self) -> u8 {
+        self.low = wrap_add(self.low, 1);
+        self.high = wrap_add(self.high, self.page[self.low as usize]);
+        self.high
+    }
+
+    fn save(&self) -> Vec<u8> {
+        vec![self.low, self.high]
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        if let [low, high] = *state {
+            self.low = low;
+            self.high = high;
+        }
+    }
+}
+
+/**
+ * Replays a fixed sequence of bytes, starting over once it runs out.
+ * Useful for tests that want to force particular random values.
+ */
+pub struct ScriptedRng {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl ScriptedRng {
+    pub fn new(bytes: Vec<u8>) -> Result<Self, String> {
+        if bytes.is_empty() {
+            return Err(String::from("Random script is empty"));
+        }
+
+        Ok(ScriptedRng { bytes, pos: 0 })
+    }
+}
+
+impl RandomSource for ScriptedRng {
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes[self.pos];
+        self.pos = (self.pos + 1) % self.bytes.len();
+        byte
+    }
+
+    fn save(&self) -> Vec<u8> {
+        (self.pos as u64).to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        if let Ok(bytes) = <[u8; 8]>::try_from(state) {
+            self.pos = u64::from_le_bytes(bytes) as usize % self.bytes.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(rng: &mut dyn RandomSource, n: usize) -> Vec<u8> {
+        (0..n).map(|_| rng.next_byte()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_bytes() {
+        let a = bytes(&mut SeededRng::new(42), 64);
+        assert_eq!(a, bytes(&mut SeededRng::new(42), 64));
+        assert_ne!(a, bytes(&mut SeededRng::new(43), 64));
+    }
+
+    #[test]
+    fn zero_seed_is_not_stuck() {
+        let zeros = bytes(&mut SeededRng::new(0), 64);
+        assert!(zeros.iter().any(|&byte| byte != 0));
+    }
+
+    #[test]
+    fn seeded_restores_saved_state() {
+        let mut rng = SeededRng::new(7);
+        bytes(&mut rng, 10);
+        let saved = rng.save();
+        let next = bytes(&mut rng, 10);
+
+        let mut other = SeededRng::new(8);
+        other.restore(&saved);
+        assert_eq!(bytes(&mut other, 10), next);
+
+        // broken states are ignored
+        other.restore(&[1, 2, 3]);
+        other.restore(&[0; 8]);
+        assert_eq!(other.save(), rng.save());
+    }
+
+    #[test]
+    fn vip_adds_the_code_page() {
+        let mut page = [0u8; 256];
+        page[1] = 3;
+        page[2] = 5;
+        page[3] = 0xFF;
+
+        let mut rng = VipRng::new(&page, 0x1000).unwrap();
+        assert_eq!(bytes(&mut rng, 3), vec![0x13, 0x18, 0x17]);
+
+        let mut interpreter = vec![0u8; 256];
+        interpreter.extend_from_slice(&page);
+        let mut rng = VipRng::new(&interpreter, 0x1000).unwrap();
+        assert_eq!(bytes(&mut rng, 3), vec![0x13, 0x18, 0x17]);
+
+        assert!(VipRng::new(&[0; 100], 0).is_err());
+    }
+
+    #[test]
+    fn scripted_repeats() {
+        let mut rng = ScriptedRng::new(vec![1, 2, 3]).unwrap();
+        assert_eq!(bytes(&mut rng, 7), vec![1, 2, 3, 1, 2, 3, 1]);
+
+        let saved = rng.save();
+        let mut other = ScriptedRng::new(vec![1, 2, 3]).unwrap();
+        other.restore(&saved);
+        assert_eq!(other.next_byte(), 2);
+
+        assert!(ScriptedRng::new(Vec::new()).is_err());
+    }
+}
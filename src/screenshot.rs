@@ -0,0 +1,28 @@
+use crate::frame::Frame;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/**
+ * save a frame as a PNG file.
+ * every pixel of the frame becomes `scale` x `scale` pixels in the image.
+ */
+pub fn save_png(frame: &Frame, scale: usize, path: &Path) -> Result<(), String> {
+    let frame = frame.scale(scale);
+    let to_string = |e: png::EncodingError| format!("{}: {}", path.display(), e);
+
+    let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        frame.width as u32,
+        frame.height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(to_string)?;
+    writer
+        .write_image_data(&frame.to_rgb24())
+        .map_err(to_string)?;
+    writer.finish().map_err(to_string)
+}
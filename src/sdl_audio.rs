@@ -0,0 +1,112 @@
+use crate::audio::AudioSink;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// samples the sound card asks for at a time, about 12ms at 44.1kHz
+const DEVICE_BUFFER: u16 = 512;
+
+// most sound buffered ahead of the sound card, in seconds. when the
+// emulator runs ahead of the sound card, the oldest samples are dropped
+// so the sound doesn't lag further and further behind
+const MAX_LATENCY: f32 = 0.1;
+
+/**
+ * Plays emulated sound through SDL.
+ * The emulator writes samples into a queue the sound card plays from.
+ */
+pub struct SdlAudio {
+    device: AudioDevice<Playback>,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+}
+
+impl SdlAudio {
+    pub fn new(ctx: &Sdl) -> Result<Self, String> {
+        let audio = ctx.audio()?;
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+        let desired = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: Some(DEVICE_BUFFER),
+        };
+        let device = audio.open_playback(None, &desired, |_| Playback {
+            queue: Arc::clone(&queue),
+            last: 0.0,
+        })?;
+        let sample_rate = device.spec().freq as u32;
+        device.resume();
+
+        Ok(SdlAudio {
+            device,
+            queue,
+            sample_rate,
+        })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn buffered(&self) -> Option<Duration> {
+        let queued = self.queue.lock().ok()?.len();
+        Some(Duration::from_secs(queued as u64) / self.sample_rate)
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        let max = (MAX_LATENCY * self.sample_rate as f32) as usize;
+
+        let mut queue = match self.queue.lock() {
+            Ok(queue) => queue,
+            // the sound card thread panicked, nothing to play to
+            Err(_) => return,
+        };
+        queue.extend(samples.iter());
+
+        if queue.len() > max {
+            let excess = queue.len() - max;
+            queue.drain(..excess);
+        }
+    }
+}
+
+impl Drop for SdlAudio {
+    fn drop(&mut self) {
+        self.device.pause();
+    }
+}
+
+// runs on the sound card's thread
+struct Playback {
+    queue: Arc<Mutex<VecDeque<f32>>>,
+
+    // latest sample played
+    last: f32,
+}
+
+impl AudioCallback for Playback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut queue = self.queue.lock().ok();
+
+        for sample in out.iter_mut() {
+            // when the emulator falls behind, fade out instead of
+            // dropping to silence, which would click
+            self.last = match queue.as_mut().and_then(|queue| queue.pop_front()) {
+                Some(next) => next,
+                None => self.last * 0.99,
+            };
+            *sample = self.last;
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use crate::cpu::FPS;
+use crate::storage;
+use crate::synth::Pattern;
+use std::path::Path;
+
+// every state file starts with these bytes
+const MAGIC: &[u8; 4] = b"SC8S";
+const VERSION: u8 = 2;
+
+/**
+ * A snapshot of the machine, enough to continue emulation from later on.
+ *
+ * Serialized as (all numbers little endian):
+ *   "SC8S", version (1 byte)
+ *   memory (4096), V0~VF (16), I (2), PC (2), display (2048, one byte per pixel),
+ *   delay timer (1), sound timer (1), timer cycles (4), stack (16 * 2), SP (1),
+ *   RPL flags (16), random source length (2) + random source state,
+ *   XO-CHIP audio pattern loaded (1) + pattern (16), pitch (1)
+ *
+ * Version 1 files end after the random source. They play the beep.
+ */
+pub struct State {
+    pub memory: [u8; 4096],
+    pub reg: [u8; 16],
+    pub index: u16,
+    pub pc: u16,
+    pub gfx: [bool; 64 * 32],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub timer_cycles: u32,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub rpl: [u8; 16],
+    // opaque state of the CXNN random source
+    pub rng: Vec<u8>,
+    pub pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+}
+
+impl State {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8192);
+
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.extend_from_slice(&self.memory);
+        buf.extend_from_slice(&self.reg);
+        buf.extend_from_slice(&self.index.to_le_bytes());
+        buf.extend_from_slice(&self.pc.to_le_bytes());
+        buf.extend(self.gfx.iter().map(|&pixel| pixel as u8));
+        buf.push(self.delay_timer);
+        buf.push(self.sound_timer);
+        buf.extend_from_slice(&self.timer_cycles.to_le_bytes());
+        for addr in self.stack.iter() {
+            buf.extend_from_slice(&addr.to_le_bytes());
+        }
+        buf.push(self.sp);
+        buf.extend_from_slice(&self.rpl);
+        buf.extend_from_slice(&(self.rng.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&self.rng);
+        buf.push(self.pattern.is_some() as u8);
+        buf.extend_from_slice(&self.pattern.unwrap_or([0; 16]));
+        buf.push(self.pitch);
+
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { buf, pos: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err(String::from("Not a scaters state file"));
+        }
+
+        let version = reader.u8()?;
+        if version == 0 || version > VERSION {
+            return Err(format!("Unsupported state file version {}", version));
+        }
+
+        let mut state = State {
+            memory: [0; 4096],
+            reg: [0; 16],
+            index: 0,
+            pc: 0,
+            gfx: [false; 64 * 32],
+            delay_timer: 0,
+            sound_timer: 0,
+            timer_cycles: 0,
+            stack: [0; 16],
+            sp: 0,
+            rpl: [0; 16],
+            rng: Vec::new(),
+            pattern: None,
+            pitch: Pattern::DEFAULT_PITCH,
+        };
+
+        state.memory.copy_from_slice(reader.take(4096)?);
+        state.reg.copy_from_slice(reader.take(16)?);
+        state.index = reader.u16()?;
+        state.pc = reader.u16()?;
+        for (pixel, &byte) in state.gfx.iter_mut().zip(reader.take(64 * 32)?) {
+            *pixel = byte != 0;
+        }
+        state.delay_timer = reader.u8()?;
+        state.sound_timer = reader.u8()?;
+        state.timer_cycles = reader.u32()?;
+        for addr in state.stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        state.sp = reader.u8()?;
+        state.rpl.copy_from_slice(reader.take(16)?);
+        let rng_len = reader.u16()? as usize;
+        state.rng = reader.take(rng_len)?.to_vec();
+
+        if version >= 2 {
+            let loaded = reader.u8()? != 0;
+            let mut pattern = [0; 16];
+            pattern.copy_from_slice(reader.take(16)?);
+            if loaded {
+                state.pattern = Some(pattern);
+            }
+            state.pitch = reader.u8()?;
+        }
+
+        state.check()?;
+
+        Ok(state)
+    }
+
+    // reject values the CPU cannot run from, e.g. in a corrupt file
+    fn check(&self) -> Result<(), String> {
+        // an instruction is 2 bytes, both have to be in memory
+        let in_memory = |addr: u16| (addr as usize) < self.memory.len() - 1;
+
+        if self.sp as usize > self.stack.len() {
+            return Err(format!("Invalid stack pointer {}", self.sp));
+        }
+        if !in_memory(self.pc) {
+            return Err(format!("Invalid program counter {:#05x}", self.pc));
+        }
+        if self.index as usize >= self.memory.len() {
+            return Err(format!("Invalid index register {:#05x}", self.index));
+        }
+        if let Some(&addr) = self.stack[..self.sp as usize]
+            .iter()
+            .find(|&&addr| !in_memory(addr))
+        {
+            return Err(format!("Invalid return address {:#05x}", addr));
+        }
+        // counts up to the frame rate, and starts over when the timers tick
+        if self.timer_cycles >= FPS {
+            return Err(format!("Invalid timer cycle count {}", self.timer_cycles));
+        }
+
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let buf = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        State::from_bytes(&buf).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        storage::write_file(path, &self.to_bytes())
+    }
+}
+
+// reads numbers from a state file, failing if the file is cut short
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.buf.len() {
+            return Err(String::from("State file is truncated"));
+        }
+
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        let mut state = State {
+            memory: [0; 4096],
+            reg: [0; 16],
+            index: 0x300,
+            pc: 0x202,
+            gfx: [false; 64 * 32],
+            delay_timer: 3,
+            sound_timer: 4,
+            timer_cycles: 5,
+            stack: [0; 16],
+            sp: 1,
+            rpl: [0; 16],
+            rng: vec![1, 2, 3],
+            pattern: Some([0xAA; 16]),
+            pitch: 80,
+        };
+        state.memory[0x200] = 0x12;
+        state.reg[0xF] = 1;
+        state.gfx[100] = true;
+        state.stack[0] = 0x210;
+        state
+    }
+
+    // state with the bytes at `offset` replaced, e.g. to corrupt a field
+    fn patched(offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut buf = state().to_bytes();
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        buf
+    }
+
+    // offsets of fields in the file
+    const INDEX: usize = 4 + 1 + 4096 + 16;
+    const PC: usize = INDEX + 2;
+    const SP: usize = PC + 2 + 64 * 32 + 1 + 1 + 4 + 16 * 2;
+
+    #[test]
+    fn round_trip() {
+        let state = state();
+        let loaded = State::from_bytes(&state.to_bytes()).unwrap();
+
+        assert_eq!(loaded.memory[..], state.memory[..]);
+        assert_eq!(loaded.reg, state.reg);
+        assert_eq!(loaded.index, state.index);
+        assert_eq!(loaded.pc, state.pc);
+        assert_eq!(loaded.gfx[..], state.gfx[..]);
+        assert_eq!(loaded.delay_timer, state.delay_timer);
+        assert_eq!(loaded.sound_timer, state.sound_timer);
+        assert_eq!(loaded.timer_cycles, state.timer_cycles);
+        assert_eq!(loaded.stack, state.stack);
+        assert_eq!(loaded.sp, state.sp);
+        assert_eq!(loaded.rng, state.rng);
+        assert_eq!(loaded.pattern, state.pattern);
+        assert_eq!(loaded.pitch, state.pitch);
+    }
+
+    #[test]
+    fn version_1_plays_the_beep() {
+        let mut buf = state().to_bytes();
+        buf[4] = 1;
+        buf.truncate(buf.len() - 18);
+
+        let loaded = State::from_bytes(&buf).unwrap();
+        assert_eq!(loaded.pattern, None);
+        assert_eq!(loaded.pitch, Pattern::DEFAULT_PITCH);
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut buf = state().to_bytes();
+        buf[0] = b'X';
+        assert!(State::from_bytes(&buf).is_err());
+
+        let mut buf = state().to_bytes();
+        buf[4] = VERSION + 1;
+        assert!(State::from_bytes(&buf).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated() {
+        let buf = state().to_bytes();
+        for len in [0, 5, SP, buf.len() - 1] {
+            assert!(State::from_bytes(&buf[..len]).is_err(), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range() {
+        assert!(State::from_bytes(&patched(SP, &[17])).is_err());
+        assert!(State::from_bytes(&patched(SP, &[200])).is_err());
+        assert!(State::from_bytes(&patched(PC, &0xFFFFu16.to_le_bytes())).is_err());
+        assert!(State::from_bytes(&patched(PC, &0x0FFFu16.to_le_bytes())).is_err());
+        assert!(State::from_bytes(&patched(INDEX, &0x1000u16.to_le_bytes())).is_err());
+        // return address in use
+        assert!(State::from_bytes(&patched(SP - 32, &0x2000u16.to_le_bytes())).is_err());
+        assert!(State::from_bytes(&patched(SP - 36, &u32::MAX.to_le_bytes())).is_err());
+        assert!(State::from_bytes(&patched(SP - 36, &FPS.to_le_bytes())).is_err());
+
+        assert!(State::from_bytes(&patched(SP, &[16])).is_ok());
+        assert!(State::from_bytes(&patched(PC, &0x0FFEu16.to_le_bytes())).is_ok());
+        assert!(State::from_bytes(&patched(INDEX, &0x0FFFu16.to_le_bytes())).is_ok());
+        assert!(State::from_bytes(&patched(SP - 36, &(FPS - 1).to_le_bytes())).is_ok());
+    }
+}
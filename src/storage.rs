@@ -0,0 +1,150 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/**
+ * Directory where scaters keeps data that should survive restarts,
+ * e.g. the RPL flags of each ROM.
+ *
+ * $XDG_DATA_HOME/scaters, or ~/.local/share/scaters if it is not set.
+ * %APPDATA%\scaters on Windows.
+ */
+pub fn data_dir() -> Result<PathBuf, String> {
+    let base = if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = env::var_os("APPDATA") {
+        PathBuf::from(dir)
+    } else if let Some(home) = env::var_os("HOME") {
+        PathBuf::from(home).join(".local").join("share")
+    } else {
+        return Err(String::from("Could not find a directory to store data in"));
+    };
+
+    Ok(base.join("scaters"))
+}
+
+/**
+ * Directory of the config file.
+ *
+ * $XDG_CONFIG_HOME/scaters, or ~/.config/scaters if it is not set.
+ * %APPDATA%\scaters on Windows.
+ */
+pub fn config_dir() -> Result<PathBuf, String> {
+    let base = if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = env::var_os("APPDATA") {
+        PathBuf::from(dir)
+    } else if let Some(home) = env::var_os("HOME") {
+        PathBuf::from(home).join(".config")
+    } else {
+        return Err(String::from("Could not find the config directory"));
+    };
+
+    Ok(base.join("scaters"))
+}
+
+/**
+ * Identify a ROM by its contents, so that renamed copies share their data.
+ * This is 64-bit FNV-1a, printed as 16 hex digits.
+ */
+pub fn rom_hash(rom: &[u8]) -> String {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for &byte in rom {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+fn flags_path(rom_hash: &str) -> Result<PathBuf, String> {
+    Ok(data_dir()?
+        .join("flags")
+        .join(format!("{}.flags", rom_hash)))
+}
+
+/**
+ * read the RPL flags saved for a ROM.
+ * Ok(None) means that nothing was saved yet.
+ */
+pub fn load_flags(rom_hash: &str) -> Result<Option<Vec<u8>>, String> {
+    let path = flags_path(rom_hash)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    fs::read(&path)
+        .map(Some)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn save_flags(rom_hash: &str, flags: &[u8]) -> Result<(), String> {
+    let path = flags_path(rom_hash)?;
+    write_file(&path, flags)
+}
+
+// file for save state `slot` of a ROM
+pub fn state_path(rom_hash: &str, slot: u8) -> Result<PathBuf, String> {
+    Ok(data_dir()?
+        .join("states")
+        .join(format!("{}.{}.state", rom_hash, slot)))
+}
+
+// keymap for a ROM, by file name so that it is easy to write by hand
+pub fn rom_keymap_path(rom_name: &str) -> Result<PathBuf, String> {
+    Ok(config_dir()?
+        .join("keymaps")
+        .join(format!("{}.toml", rom_name)))
+}
+
+// state written when the window is closed, to continue from next time
+pub fn autosave_path(rom_hash: &str) -> Result<PathBuf, String> {
+    Ok(data_dir()?
+        .join("states")
+        .join(format!("{}.auto.state", rom_hash)))
+}
+
+// write a file, creating its parent directories as needed
+pub fn write_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+
+    fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/**
+ * current time (UTC) for file names, e.g. 20261018-165417
+ */
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // days since 1970-01-01 to a calendar date
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
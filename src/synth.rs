@@ -0,0 +1,230 @@
+/**
+ * Shape of the tone
+ *
+ * Square:   the classic buzzer
+ * Triangle: softer, like the NES
+ * Sine:     a pure tone
+ * Noise:    random levels, changing `frequency` times a second
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    Noise,
+}
+
+impl Waveform {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!(
+                "Unknown waveform {}, expected square, triangle, sine or noise",
+                value
+            )),
+        }
+    }
+}
+
+/**
+ * What the tone sounds like
+ *
+ * frequency: pitch in Hz
+ * volume:    0.0 (silent) ~ 1.0 (loudest)
+ * attack:    seconds the tone takes to fade in
+ * release:   seconds the tone takes to fade out
+ *
+ * Starting or stopping at full volume at once clicks, so attack and
+ * release should be at least a millisecond or two.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+    pub attack: f32,
+    pub release: f32,
+}
+
+impl Tone {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.frequency > 0.0 && self.frequency <= 20000.0) {
+            return Err(String::from("The frequency must be 0 ~ 20000 Hz"));
+        }
+        if !(0.0..=1.0).contains(&self.volume) {
+            return Err(String::from("The volume must be 0.0 ~ 1.0"));
+        }
+        if !(self.attack >= 0.0 && self.release >= 0.0) {
+            return Err(String::from("Attack and release can't be negative"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+            attack: 0.002,
+            release: 0.002,
+        }
+    }
+}
+
+/**
+ * XO-CHIP sound: 128 1-bit samples, most significant bit of the first
+ * byte first, played in a loop at 4000 * 2 ^ ((pitch - 64) / 48) samples
+ * per second. Pitch 64 is 4000 samples per second.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Pattern {
+    pub bits: [u8; 16],
+    pub pitch: u8,
+}
+
+impl Pattern {
+    pub const DEFAULT_PITCH: u8 = 64;
+
+    // samples (bits) per second
+    pub fn rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // bit `n` (0 ~ 127) as a level, -1.0 or 1.0
+    fn level(&self, n: usize) -> f32 {
+        if self.bits[n / 8] & (0x80 >> (n % 8)) != 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+}
+
+/**
+ * Generates a tone sample by sample, for any sample rate.
+ * Turning it on and off follows the attack and release of the tone.
+ *
+ * With a pattern, the pattern is played instead of the waveform of the
+ * tone, still at the volume and with the envelope of the tone.
+ */
+pub struct Synth {
+    pub tone: Tone,
+    pub pattern: Option<Pattern>,
+
+    // position within a period of the wave, 0.0 ~ 1.0
+    phase: f32,
+
+    // envelope, 0.0 (silent) ~ 1.0 (full volume)
+    level: f32,
+
+    // linear feedback shift register for noise, and its current output
+    lfsr: u16,
+    noise: f32,
+}
+
+impl Synth {
+    pub fn new(tone: Tone) -> Self {
+        Synth {
+            tone,
+            pattern: None,
+            phase: 0.0,
+            level: 0.0,
+            lfsr: 0xACE1,
+            noise: 1.0,
+        }
+    }
+
+    /**
+     * the next sample, -1.0 ~ 1.0.
+     * on: should the tone be sounding?
+     */
+    pub fn sample(&mut self, on: bool, sample_rate: f32) -> f32 {
+        self.level = if on {
+            (self.level + ramp_step(self.tone.attack, sample_rate)).min(1.0)
+        } else {
+            (self.level - ramp_step(self.tone.release, sample_rate)).max(0.0)
+        };
+
+        if self.level == 0.0 {
+            // start every tone at the beginning of a period
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        if let Some(pattern) = self.pattern {
+            return self.pattern_sample(&pattern, sample_rate) * self.level * self.tone.volume;
+        }
+
+        let wave = match self.tone.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Noise => self.noise,
+        };
+
+        self.phase += self.tone.frequency / sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+            self.next_noise();
+        }
+
+        wave * self.level * self.tone.volume
+    }
+
+    /**
+     * the average level of the pattern over the next 1 / sample_rate s.
+     * averaging, rather than picking the nearest bit, keeps patterns
+     * played faster than the sample rate from aliasing into other pitches.
+     * `phase` is the position in the pattern, 0.0 ~ 1.0.
+     */
+    fn pattern_sample(&mut self, pattern: &Pattern, sample_rate: f32) -> f32 {
+        let step = pattern.rate() / sample_rate;
+        let mut position = self.phase * 128.0;
+        let mut remaining = step;
+        let mut sum = 0.0;
+
+        // add up the bits covered by this sample, weighted by how much
+        // of them it covers
+        while remaining > 0.0 {
+            let bit = position.floor();
+            let take = remaining.min(bit + 1.0 - position);
+            sum += pattern.level(bit as usize % 128) * take;
+            position += take;
+            remaining -= take;
+        }
+
+        self.phase = (position / 128.0).fract();
+        sum / step
+    }
+
+    // 16 bit Galois LFSR, a cheap source of noise
+    fn next_noise(&mut self) {
+        let bit = self.lfsr & 1;
+        self.lfsr >>= 1;
+        if bit != 0 {
+            self.lfsr ^= 0xB400;
+        }
+        self.noise = if self.lfsr & 1 != 0 { 1.0 } else { -1.0 };
+    }
+}
+
+// how much the envelope moves per sample, fading over `seconds`
+fn ramp_step(seconds: f32, sample_rate: f32) -> f32 {
+    if seconds > 0.0 {
+        1.0 / (seconds * sample_rate)
+    } else {
+        1.0
+    }
+}
@@ -0,0 +1,364 @@
+use crate::keymap::Keymap;
+use crate::palette::{Palette, Rgb};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, queue, style, terminal};
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+// most terminals do not report key releases, so a key counts as released
+// once it has not been reported for this long. key repeat keeps it held,
+// but only starts after a delay, which is up to 600 ms on most systems.
+const KEY_HOLD: Duration = Duration::from_millis(700);
+
+// once the terminal repeats a key, it counts as released after this long
+// without a repeat. repeats come every 30 ~ 100 ms
+const REPEAT_GAP: Duration = Duration::from_millis(150);
+
+/**
+ * How the display is drawn with text
+ *
+ * HalfBlock: one character cell holds 1x2 pixels (▀ ▄ █), 64x16 cells for 64x32
+ * Braille:   one character cell holds 2x4 pixels (⠁ ~ ⣿), 32x8 cells for 64x32
+ */
+#[derive(Clone, Copy)]
+pub enum TermStyle {
+    HalfBlock,
+    Braille,
+}
+
+impl TermStyle {
+    // pixels covered by a single character cell
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            TermStyle::HalfBlock => (1, 2),
+            TermStyle::Braille => (2, 4),
+        }
+    }
+}
+
+// input from the terminal, already translated for the emulator
+pub enum TermInput {
+    // name of a key bound in the keymap, pressed or released
+    Key(String, bool),
+    // number of a function key, e.g. 5 for F5
+    FunctionKey(u8),
+    // Pause, or P unless it is bound to a CHIP-8 key
+    Pause,
+    Quit,
+}
+
+/**
+ * Text mode display. Draws the display into the terminal and reads the
+ * keyboard in raw mode, so it works over SSH without SDL.
+ */
+pub struct Terminal {
+    style: TermStyle,
+    stdout: Stdout,
+
+    // characters currently on screen, to only repaint what changed
+    cells: Vec<char>,
+
+    // size of the display currently on screen, in pixels
+    width: usize,
+    height: usize,
+
+    // colors currently on screen
+    palette: Option<Palette>,
+
+    // does the terminal report key releases?
+    reports_release: bool,
+
+    // keys held down, when they were last reported as pressed, and whether
+    // the terminal has repeated them yet
+    held: Vec<(String, Instant, bool)>,
+
+    // the last hotkey pressed and when, to tell repeats from presses
+    last_hotkey: Option<(KeyCode, Instant)>,
+}
+
+impl Terminal {
+    pub fn new(style: TermStyle) -> Result<Self, String> {
+        let mut stdout = io::stdout();
+
+        terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+        execute!(
+            stdout,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )
+        .map_err(|e| e.to_string())?;
+
+        // terminals that speak the kitty keyboard protocol can tell us
+        // when a key is released
+        let reports_release = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_release {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )
+            .map_err(|e| e.to_string())?;
+        }
+
+        Ok(Terminal {
+            style,
+            stdout,
+            // set up by the first draw
+            cells: Vec::new(),
+            width: 0,
+            height: 0,
+            palette: None,
+            reports_release,
+            held: Vec::new(),
+            last_hotkey: None,
+        })
+    }
+
+    /**
+     * draw the display.
+     * `shades` is the brightness of each pixel, 0 (off) ~ 255 (on).
+     * characters are either on or off, so pixels count as on from half brightness.
+     * the display is `width` pixels wide, e.g. 64 or 128.
+     */
+    pub fn draw(&mut self, shades: &[u8], width: usize, palette: &Palette) -> Result<(), String> {
+        let height = shades.len() / width;
+
+        if self.palette.as_ref() != Some(palette) || self.width != width || self.height != height {
+            self.reset(width, height, palette)?;
+        }
+
+        let (cell_width, cell_height) = self.style.cell_size();
+        let columns = width / cell_width;
+        let rows = height / cell_height;
+        let lit = |x: usize, y: usize| shades[y * width + x] >= 0x80;
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = column * cell_width;
+                let y = row * cell_height;
+
+                let c = match self.style {
+                    TermStyle::HalfBlock => half_block(&lit, x, y),
+                    TermStyle::Braille => braille(&lit, x, y),
+                };
+
+                let cell = &mut self.cells[row * columns + column];
+                if *cell == c {
+                    continue;
+                }
+                *cell = c;
+
+                queue!(
+                    self.stdout,
+                    cursor::MoveTo(column as u16, row as u16),
+                    style::Print(c)
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+
+        self.stdout.flush().map_err(|e| e.to_string())
+    }
+
+    // switch colors and size, and clear the screen so everything is repainted
+    fn reset(&mut self, width: usize, height: usize, palette: &Palette) -> Result<(), String> {
+        queue!(
+            self.stdout,
+            style::SetForegroundColor(to_color(palette.foreground())),
+            style::SetBackgroundColor(to_color(palette.background())),
+            terminal::Clear(terminal::ClearType::All)
+        )
+        .map_err(|e| e.to_string())?;
+
+        let (cell_width, cell_height) = self.style.cell_size();
+        self.cells = vec![' '; (width / cell_width) * (height / cell_height)];
+        self.width = width;
+        self.height = height;
+        self.palette = Some(palette.clone());
+
+        Ok(())
+    }
+
+    // show a message on the line below the display
+    pub fn status(&mut self, message: &str) -> Result<(), String> {
+        let (_, cell_height) = self.style.cell_size();
+        let row = (self.height / cell_height) as u16;
+
+        execute!(
+            self.stdout,
+            cursor::MoveTo(0, row),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::Print(message)
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /**
+     * is this the first press of a hotkey, and not a repeat?
+     * hotkeys fire once, however long they are held.
+     */
+    fn first_press(&mut self, code: KeyCode, kind: KeyEventKind) -> bool {
+        if self.reports_release {
+            return kind == KeyEventKind::Press;
+        }
+
+        // repeats look like presses here. a press of the same key soon
+        // after the last one is taken for a repeat
+        let repeat =
+            matches!(self.last_hotkey, Some((last, at)) if last == code && at.elapsed() < KEY_HOLD);
+        self.last_hotkey = Some((code, Instant::now()));
+        !repeat
+    }
+
+    /**
+     * read all pending input without blocking.
+     * Esc and Ctrl+C quit, since raw mode swallows the interrupt signal.
+     * the terminal only tells which character was typed, so keys are looked
+     * up in `keymap` by their label, e.g. Q, and not by their position.
+     * the azerty preset translates labels back to positions.
+     */
+    pub fn poll_input(&mut self, keymap: &Keymap) -> Result<Vec<TermInput>, String> {
+        let mut inputs = Vec::new();
+
+        while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+            let key = match event::read().map_err(|e| e.to_string())? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+            let pressed = key.kind != KeyEventKind::Release;
+            let hotkey = matches!(
+                key.code,
+                KeyCode::F(_) | KeyCode::Pause | KeyCode::Char('p')
+            ) && self.first_press(key.code, key.kind);
+
+            match key.code {
+                KeyCode::Esc => inputs.push(TermInput::Quit),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    inputs.push(TermInput::Quit)
+                }
+                KeyCode::F(n) if hotkey => inputs.push(TermInput::FunctionKey(n)),
+                KeyCode::Pause if hotkey => inputs.push(TermInput::Pause),
+                code => match key_name(code, keymap).filter(|name| keymap.binds(name)) {
+                    Some(name) => {
+                        let held = self.held.iter().position(|(held, _, _)| *held == name);
+                        match (held, pressed) {
+                            // key repeat
+                            (Some(i), true) => {
+                                self.held[i].1 = Instant::now();
+                                self.held[i].2 = true;
+                            }
+                            (None, true) => {
+                                self.held.push((name.clone(), Instant::now(), false));
+                                inputs.push(TermInput::Key(name, true));
+                            }
+                            (Some(i), false) => {
+                                self.held.remove(i);
+                                inputs.push(TermInput::Key(name, false));
+                            }
+                            (None, false) => (),
+                        }
+                    }
+                    None if code == KeyCode::Char('p') && hotkey => inputs.push(TermInput::Pause),
+                    None => (),
+                },
+            }
+        }
+
+        // release keys the terminal has stopped repeating. a single press
+        // could still be followed by repeats until the repeat delay is over
+        if !self.reports_release {
+            let (released, held) = self.held.drain(..).partition(|&(_, since, repeated)| {
+                since.elapsed() > if repeated { REPEAT_GAP } else { KEY_HOLD }
+            });
+            self.held = held;
+
+            for (name, _, _) in released {
+                inputs.push(TermInput::Key(name, false));
+            }
+        }
+
+        Ok(inputs)
+    }
+}
+
+impl Drop for Terminal {
+    // give the terminal back in the state we found it
+    fn drop(&mut self) {
+        if self.reports_release {
+            execute!(self.stdout, PopKeyboardEnhancementFlags).ok();
+        }
+        execute!(
+            self.stdout,
+            style::ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        )
+        .ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+fn to_color(rgb: Rgb) -> style::Color {
+    style::Color::Rgb {
+        r: rgb.0,
+        g: rgb.1,
+        b: rgb.2,
+    }
+}
+
+// the pixel at (x, y) and the one below it
+fn half_block(lit: &impl Fn(usize, usize) -> bool, x: usize, y: usize) -> char {
+    match (lit(x, y), lit(x, y + 1)) {
+        (false, false) => ' ',
+        (true, false) => '▀',
+        (false, true) => '▄',
+        (true, true) => '█',
+    }
+}
+
+/**
+ * the 2x4 pixels starting at (x, y).
+ * braille characters number their dots as:
+ *   1 4
+ *   2 5
+ *   3 6
+ *   7 8
+ * and dot n is bit n-1 of the offset from U+2800
+ */
+fn braille(lit: &impl Fn(usize, usize) -> bool, x: usize, y: usize) -> char {
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let mut bits = 0;
+    for (dy, row) in DOTS.iter().enumerate() {
+        for (dx, bit) in row.iter().enumerate() {
+            if lit(x + dx, y + dy) {
+                bits |= bit;
+            }
+        }
+    }
+
+    // an empty braille cell looks the same as a space
+    if bits == 0 {
+        return ' ';
+    }
+
+    std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+}
+
+// name of a key like SDL calls it, for `keymap`
+fn key_name(code: KeyCode, keymap: &Keymap) -> Option<String> {
+    match code {
+        KeyCode::Char(c) => Some(keymap.typed_key_name(c)),
+        KeyCode::Up => Some(String::from("Up")),
+        KeyCode::Down => Some(String::from("Down")),
+        KeyCode::Left => Some(String::from("Left")),
+        KeyCode::Right => Some(String::from("Right")),
+        KeyCode::Enter => Some(String::from("Return")),
+        KeyCode::Tab => Some(String::from("Tab")),
+        KeyCode::Backspace => Some(String::from("Backspace")),
+        _ => None,
+    }
+}
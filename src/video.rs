@@ -0,0 +1,107 @@
+use crate::frame::Frame;
+use crate::palette::Rgb;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/**
+ * Writes the display as an uncompressed YUV4MPEG2 (y4m) video at 60 fps,
+ * for encoding with external tools, e.g.
+ *   scaters --display none --frames 600 --video-out - pong.ch8 | ffmpeg -i - pong.mp4
+ *
+ * One video frame is written per emulated frame (1/60 s), so the video
+ * plays at the speed of the emulated machine however fast it really ran.
+ *
+ * Frames are stored without chroma subsampling (4:4:4), as subsampling
+ * smears the colors of single pixels.
+ */
+pub struct VideoWriter {
+    out: Box<dyn Write>,
+    pub name: String,
+
+    // every pixel becomes `scale` x `scale` pixels in the video
+    scale: usize,
+
+    // size of the video, set by the first frame
+    size: Option<(usize, usize)>,
+}
+
+impl VideoWriter {
+    // write to the file at `path`, or to stdout if `path` is "-"
+    pub fn create(path: &str, scale: usize) -> Result<Self, String> {
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            Box::new(BufWriter::new(file))
+        };
+
+        Ok(VideoWriter {
+            out,
+            name: if path == "-" {
+                String::from("stdout")
+            } else {
+                path.to_string()
+            },
+            scale: scale.max(1),
+            size: None,
+        })
+    }
+
+    // add the display at the end of a 1/60 s frame
+    pub fn frame(&mut self, frame: &Frame) -> Result<(), String> {
+        let frame = frame.scale(self.scale);
+        let size = (frame.width, frame.height);
+
+        match self.size {
+            None => {
+                let header = format!(
+                    "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444\n",
+                    frame.width, frame.height
+                );
+                self.write(header.as_bytes())?;
+                self.size = Some(size);
+            }
+            // y4m can't change resolution midway
+            Some(video_size) if video_size != size => {
+                return Err(String::from("The resolution changed during the video"));
+            }
+            Some(_) => (),
+        }
+
+        // planes of Y, then Cb, then Cr
+        let pixels = frame.pixels.len();
+        let mut data = vec![0; 6 + pixels * 3];
+        data[..6].copy_from_slice(b"FRAME\n");
+        for (i, &pixel) in frame.pixels.iter().enumerate() {
+            let (y, cb, cr) = to_ycbcr(pixel);
+            data[6 + i] = y;
+            data[6 + pixels + i] = cb;
+            data[6 + pixels * 2 + i] = cr;
+        }
+
+        self.write(&data)
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        let name = self.name.clone();
+        self.out.flush().map_err(|e| format!("{}: {}", name, e))
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        let name = &self.name;
+        self.out
+            .write_all(data)
+            .map_err(|e| format!("{}: {}", name, e))
+    }
+}
+
+// BT.601 with video levels (Y 16 ~ 235), which players assume for y4m
+fn to_ycbcr(rgb: Rgb) -> (u8, u8, u8) {
+    let (r, g, b) = (rgb.0 as i32, rgb.1 as i32, rgb.2 as i32);
+
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let cb = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let cr = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+
+    (y as u8, cb as u8, cr as u8)
+}
@@ -0,0 +1,101 @@
+use crate::audio::AudioSink;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+const SAMPLE_RATE: u32 = 44100;
+
+/**
+ * Writes the sound into a WAV file, 16 bit mono PCM at 44.1kHz.
+ *
+ * The sound is generated in emulated time, so the file has exactly as
+ * many samples as the emulated time calls for, even when the emulator
+ * runs headless, far faster than real time.
+ */
+pub struct WavSink {
+    out: BufWriter<File>,
+    path: String,
+
+    // samples written so far
+    samples: u32,
+
+    // first error while writing, reported by `finish`
+    error: Option<String>,
+}
+
+impl WavSink {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut sink = WavSink {
+            out: BufWriter::new(file),
+            path: path.to_string(),
+            samples: 0,
+            error: None,
+        };
+
+        // the sizes are filled in by `finish`, once they are known
+        sink.write_header(0)?;
+        Ok(sink)
+    }
+
+    fn write_header(&mut self, samples: u32) -> Result<(), String> {
+        let data_size = samples * 2;
+        let mut header = Vec::with_capacity(44);
+
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(36 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes()); // size of this chunk
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&1u16.to_le_bytes()); // mono
+        header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // bytes per second
+        header.extend_from_slice(&2u16.to_le_bytes()); // bytes per sample
+        header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+
+        self.out
+            .write_all(&header)
+            .map_err(|e| format!("{}: {}", self.path, e))
+    }
+}
+
+impl AudioSink for WavSink {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        match self.out.write_all(&bytes) {
+            Ok(()) => self.samples += samples.len() as u32,
+            Err(e) => self.error = Some(format!("{}: {}", self.path, e)),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        self.out
+            .seek(SeekFrom::Start(0))
+            .map_err(|e| format!("{}: {}", self.path, e))?;
+        self.write_header(self.samples)?;
+        self.out
+            .flush()
+            .map_err(|e| format!("{}: {}", self.path, e))
+    }
+}